@@ -0,0 +1,303 @@
+// config.rs - Startup settings, optionally read from a TOML file and
+// overridden by command-line arguments
+
+use std::path::PathBuf;
+
+use crate::watermark::Corner;
+use crate::window::Scaling;
+use crate::ImageFormat;
+
+#[cfg(feature = "serde")]
+const CONFIG_FILE: &str = "juggler.toml"; // Read from the current directory
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Config {
+    pub to_files: bool,               // Render image files instead of opening a window
+    pub target_fps: f64,              // Window render size is selected to reach this
+    pub render_size: Option<usize>,   // Square render size, None: select automatically
+    pub output_size: Option<usize>,   // Size of rendered files, None: render size
+    pub num_frames: Option<usize>,    // Frames to render to files, None: 15 seconds
+    pub output_format: ImageFormat,   // Format of rendered files
+    pub output_dir: PathBuf,          // Directory of rendered files
+    pub num_threads: Option<usize>,   // Render threads, None: one per logical CPU
+    pub samples_per_axis: usize,      // Supersampling, 1: one sample per pixel
+    pub gamma: f64,                   // Display gamma, 1.0: write linear colors
+    pub scene: String,                // Name of the first scene, e.g., "juggler"
+    pub span_delay_ms: Option<u64>,   // Simulate a slow computer, sleep per span
+    pub transparent_background: bool, // Alpha 0 where the background shows
+    pub export_depth: bool,           // Also write depth files, distance per pixel
+    pub seamless_loop: bool,          // Render files as a loop, replacing num_frames
+    pub watermark: Option<(PathBuf, Corner, f64)>, // Logo over rendered files: PNG, corner, opacity
+    pub watermark_margin: usize,      // Pixels between the logo and the image edges
+    pub num_windows: usize,           // Windows side by side, later ones view from above
+    pub fixed_step: bool,             // Windows advance 1/target_fps per frame, like files
+    pub benchmark: bool,              // Print render times by size and span size, then exit
+    pub turntable: bool,              // Render files orbiting the juggler, not its camera
+    pub parallel_frames: bool,        // Render files several frames at a time, one thread each
+    pub dump_scene: Option<f64>,      // Print the scene at this time in seconds, then exit
+    pub scaling: Scaling,             // How windows scale the rendered image to their size
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            to_files: false,
+            target_fps: crate::TARGET_FPS,
+            render_size: None,
+            output_size: None,
+            num_frames: None,
+            output_format: ImageFormat::Ppm,
+            output_dir: PathBuf::from("."),
+            num_threads: None,
+            samples_per_axis: 1,
+            gamma: 1.0,
+            scene: "juggler".to_string(),
+            span_delay_ms: None,
+            transparent_background: false,
+            export_depth: false,
+            seamless_loop: false,
+            watermark: None,
+            watermark_margin: 16,
+            num_windows: 1,
+            fixed_step: false,
+            benchmark: false,
+            turntable: false,
+            parallel_frames: false,
+            dump_scene: None,
+            scaling: Scaling::NearestNeighbor,
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+Usage: juggler-in-rust [options]
+
+Options:
+  --size WxH           Render size, W and H equal as non-square sizes are not
+                       yet supported (default: fit the frame rate)
+  --output-size WxH    Size of rendered files, scaled from the render size
+  --frames N           Number of frames to render to files
+  --fps F              Target frame rate, or frame rate of rendered files
+  --out DIR            Directory of rendered files
+  --format ppm|bmp|png Format of rendered files. Video, mp4 or gif, is not yet
+                       supported: convert the files, or record from the window
+  --scene NAME         First scene: juggler, simple or test
+  --threads N          Number of render threads
+  --gamma G            Display gamma, e.g., 2.2 (default: 1.0, linear colors)
+  --headless           Render to files instead of opening a window
+  --windows N          Open N windows, the second and later viewing from above
+  --fixed-step         Advance window frames by 1/fps, to match rendered files
+  --scaling nearest|bilinear
+                       Scale the image to the window blocky or smooth
+                       (default: nearest)
+  --benchmark          Print render times for all sizes and span sizes as CSV
+  --dump-scene [SECS]  Print the scene at SECS seconds (default: 0), then exit
+  --span-delay MS      Simulate a slow computer, sleeping before each span
+  --transparent        Transparent background, for rendering PNG files
+  --depth              Also write the camera distance per pixel, as PFM files
+  --loop               Render the fewest frames that loop seamlessly
+  --turntable          Render files of one orbit around the juggler (default: 10 s)
+  --parallel-frames    Render files several frames at a time, one thread each
+  --watermark FILE     Draw a PNG logo in the bottom-right corner of rendered files
+  --help               Show this help";
+
+pub fn apply_args(config: &mut Config, args: impl Iterator<Item = String>) -> Result<(), String> {
+    // Command-line arguments override settings read from the file
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "--size" => config.render_size = Some(parse_size(&value()?)?),
+            "--output-size" => config.output_size = Some(parse_size(&value()?)?),
+            "--frames" => config.num_frames = Some(parse(&arg, &value()?)?),
+            "--fps" => {
+                let fps: f64 = parse(&arg, &value()?)?;
+                if !fps.is_finite() || fps <= 0.0 {
+                    return Err(format!("Invalid frame rate {fps}"));
+                }
+                config.target_fps = fps;
+            }
+            "--out" => config.output_dir = PathBuf::from(value()?),
+            "--format" => {
+                config.output_format = match value()?.as_str() {
+                    "ppm" => ImageFormat::Ppm,
+                    "bmp" => ImageFormat::Bmp,
+                    "png" => ImageFormat::Png,
+                    video @ ("mp4" | "gif") => {
+                        return Err(format!("Format {video} is not yet supported"))
+                    }
+                    other => return Err(format!("Unsupported format {other}")),
+                }
+            }
+            "--scene" => config.scene = value()?,
+            "--gamma" => {
+                let gamma: f64 = parse(&arg, &value()?)?;
+                if !gamma.is_finite() || gamma <= 0.0 {
+                    return Err(format!("Invalid gamma {gamma}"));
+                }
+                config.gamma = gamma;
+            }
+            "--threads" => config.num_threads = Some(parse::<usize>(&arg, &value()?)?.max(1)),
+            "--headless" => config.to_files = true,
+            "--windows" => config.num_windows = parse::<usize>(&arg, &value()?)?.max(1),
+            "--fixed-step" => config.fixed_step = true,
+            "--scaling" => {
+                config.scaling = match value()?.as_str() {
+                    "nearest" => Scaling::NearestNeighbor,
+                    "bilinear" => Scaling::Bilinear,
+                    other => return Err(format!("Unsupported scaling {other}")),
+                }
+            }
+            "--benchmark" => config.benchmark = true,
+            "--dump-scene" => {
+                // The time is optional, the next option is not one
+                let secs: f64 = match args.next_if(|next| !next.starts_with("--")) {
+                    Some(secs) => parse(&arg, &secs)?,
+                    None => 0.0,
+                };
+                if !secs.is_finite() || secs < 0.0 {
+                    return Err(format!("Invalid time {secs}"));
+                }
+                config.dump_scene = Some(secs);
+            }
+            "--span-delay" => config.span_delay_ms = Some(parse(&arg, &value()?)?),
+            "--transparent" => config.transparent_background = true,
+            "--depth" => config.export_depth = true,
+            "--loop" => config.seamless_loop = true,
+            "--turntable" => config.turntable = true,
+            "--parallel-frames" => config.parallel_frames = true,
+            "--watermark" => {
+                // Corner and opacity from the settings file, if given there
+                let (corner, opacity) = config
+                    .watermark
+                    .take()
+                    .map_or((Corner::BottomRight, 1.0), |(_, corner, opacity)| {
+                        (corner, opacity)
+                    });
+                config.watermark = Some((PathBuf::from(value()?), corner, opacity));
+            }
+            "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ => return Err(format!("Unknown argument {arg}")),
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_size(value: &str) -> Result<usize, String> {
+    // WxH, with W and H equal until the file path renders other sizes
+    let (width, height) = value
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| format!("Invalid size {value}"))?;
+    if width != height {
+        return Err(format!("Non-square size {value} is not yet supported"));
+    }
+    Ok(width)
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {value} for {arg}"))
+}
+
+#[cfg(feature = "serde")]
+pub fn load() -> Config {
+    // Settings missing from the file keep their defaults
+    let text = match std::fs::read_to_string(CONFIG_FILE) {
+        Ok(text) => text,
+        Err(_) => return Config::default(), // No file, no problem
+    };
+    match toml::from_str(&text) {
+        Ok(config) => {
+            log::info!("Loaded settings from {CONFIG_FILE}");
+            config
+        }
+        Err(err) => {
+            log::warn!("Failed to parse {CONFIG_FILE}, using defaults: {err}");
+            Config::default()
+        }
+    }
+}
+
+#[cfg(not(feature = "serde"))]
+pub fn load() -> Config {
+    // Reading a config file needs the serde feature
+    Config::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(args: &[&str]) -> Result<Config, String> {
+        let mut config = Config::default();
+        apply_args(&mut config, args.iter().map(|arg| arg.to_string()))?;
+        Ok(config)
+    }
+
+    #[test]
+    fn unsupported_values_are_rejected() {
+        // Named as not yet supported, rather than silently replaced
+        assert_eq!(
+            apply(&["--format", "mp4"]).unwrap_err(),
+            "Format mp4 is not yet supported"
+        );
+        assert_eq!(
+            apply(&["--format", "gif"]).unwrap_err(),
+            "Format gif is not yet supported"
+        );
+        assert_eq!(
+            apply(&["--size", "64x48"]).unwrap_err(),
+            "Non-square size 64x48 is not yet supported"
+        );
+        assert_eq!(
+            apply(&["--format", "tga"]).unwrap_err(),
+            "Unsupported format tga"
+        );
+
+        let config = apply(&["--size", "64x64", "--format", "png"]).unwrap();
+        assert_eq!(config.render_size, Some(64));
+        assert!(matches!(config.output_format, ImageFormat::Png));
+    }
+
+    #[test]
+    fn scaling_is_parsed() {
+        assert_eq!(apply(&[]).unwrap().scaling, Scaling::NearestNeighbor);
+        assert_eq!(
+            apply(&["--scaling", "bilinear"]).unwrap().scaling,
+            Scaling::Bilinear
+        );
+        assert_eq!(
+            apply(&["--scaling", "cubic"]).unwrap_err(),
+            "Unsupported scaling cubic"
+        );
+    }
+
+    #[test]
+    fn dump_scene_time_is_optional() {
+        assert_eq!(apply(&["--dump-scene"]).unwrap().dump_scene, Some(0.0));
+        assert_eq!(
+            apply(&["--dump-scene", "2.5"]).unwrap().dump_scene,
+            Some(2.5)
+        );
+
+        // An option after it is not taken for the time
+        let config = apply(&["--dump-scene", "--scene", "simple"]).unwrap();
+        assert_eq!(config.dump_scene, Some(0.0));
+        assert_eq!(config.scene, "simple");
+
+        assert!(apply(&["--dump-scene", "soon"]).is_err());
+        assert_eq!(apply(&[]).unwrap().dump_scene, None);
+    }
+}
@@ -0,0 +1,97 @@
+// fps_counter.rs - Frame rate counter with frame time statistics
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const FPS_REFRESH_PERIOD: f64 = 0.25; // Update FPS counter this often
+const FRAME_HISTORY_SIZE: usize = 120; // Number of recent frame times to keep
+
+pub struct FPSCounter {
+    last_update_time: Option<Instant>,
+    durations: Duration,
+    num_frames: usize,
+    history: VecDeque<Duration>, // Rolling window of recent frame times
+}
+
+impl FPSCounter {
+    pub fn new() -> Self {
+        Self {
+            last_update_time: None,
+            durations: Duration::ZERO,
+            num_frames: 0,
+            history: VecDeque::with_capacity(FRAME_HISTORY_SIZE),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn new_frame(&mut self, frame_duration: Duration) -> Option<f64> {
+        let now = Instant::now();
+
+        // Remember frame time, dropping the oldest one when full
+        if self.history.len() == FRAME_HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame_duration);
+
+        // Calculate FPS every FPS_REFRESH_PERIOD
+        self.num_frames += 1;
+        self.durations += frame_duration;
+
+        let fps = match self.last_update_time {
+            None => {
+                // First frame, skip updating FPS
+                self.last_update_time = Some(now);
+                None
+            }
+            Some(last_time) => {
+                if now.duration_since(last_time).as_secs_f64() < FPS_REFRESH_PERIOD {
+                    // Not yet time to update FPS
+                    None
+                } else {
+                    // Time to update, calculate FPS
+                    let fps = self.num_frames as f64 / self.durations.as_secs_f64();
+
+                    self.last_update_time = Some(now);
+                    self.durations = Duration::ZERO;
+                    self.num_frames = 0;
+
+                    Some(fps)
+                }
+            }
+        };
+
+        fps
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.history.is_empty() {
+            return None;
+        }
+
+        let total: Duration = self.history.iter().sum();
+        Some(total / self.history.len() as u32)
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.history.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.history.iter().max().copied()
+    }
+
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.history.is_empty() {
+            return None;
+        }
+
+        // Nearest-rank percentile, p in range 0.0..=100.0
+        let mut sorted: Vec<Duration> = self.history.iter().copied().collect();
+        sorted.sort();
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.max(1) - 1])
+    }
+}
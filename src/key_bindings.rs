@@ -0,0 +1,226 @@
+// key_bindings.rs - Map keys to the actions they trigger in a window
+
+use winit::keyboard::{Key, NamedKey};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Quit,
+    ToggleFullscreen,
+    SetSpeed0(f64),            // Scene dependent speed 0
+    SetSpeed1(f64),            // Scene dependent speed 1
+    ToggleFreezeSpeed0,        // Speed 0 to zero and back, e.g., hold the juggling
+    ToggleFreezeSpeed1,        // Speed 1 to zero and back, e.g., hold the camera
+    ToggleOption0,             // Scene dependent option 0
+    ToggleOption1,             // Scene dependent option 1
+    AdjustLightIntensity(f64), // Step added to the key light intensity scale
+    ToggleHiddenGroup(u32),    // Bit number in scene dependent hidden_groups
+    DecreaseSupersampling,
+    IncreaseSupersampling,
+    NextScene,
+    ToggleAutoFrame,
+    ToggleLightGizmos,
+    ToggleRecording,
+    ToggleHelp,
+    TogglePowerSaver,
+    NextQualityPreset,
+    ToggleTimeJitter,
+}
+
+impl Action {
+    pub fn description(&self) -> String {
+        // Short text for the help overlay
+        match self {
+            Action::Quit => "Quit".to_string(),
+            Action::ToggleFullscreen => "Toggle fullscreen".to_string(),
+            Action::SetSpeed0(speed) => format!("Speed 0: {speed}"),
+            Action::SetSpeed1(speed) => format!("Speed 1: {speed}"),
+            Action::ToggleFreezeSpeed0 => "Freeze/unfreeze speed 0".to_string(),
+            Action::ToggleFreezeSpeed1 => "Freeze/unfreeze speed 1".to_string(),
+            Action::ToggleOption0 => "Toggle option 0".to_string(),
+            Action::ToggleOption1 => "Toggle option 1".to_string(),
+            Action::AdjustLightIntensity(step) if *step < 0.0 => "Dim key light".to_string(),
+            Action::AdjustLightIntensity(_) => "Brighten key light".to_string(),
+            Action::ToggleHiddenGroup(bit) => format!("Hide/show object group {bit}"),
+            Action::DecreaseSupersampling => "Less supersampling".to_string(),
+            Action::IncreaseSupersampling => "More supersampling".to_string(),
+            Action::NextScene => "Next scene".to_string(),
+            Action::ToggleAutoFrame => "Toggle auto-framing camera".to_string(),
+            Action::ToggleLightGizmos => "Toggle light markers".to_string(),
+            Action::ToggleRecording => "Toggle recording".to_string(),
+            Action::ToggleHelp => "Toggle this help".to_string(),
+            Action::TogglePowerSaver => "Toggle pausing when unfocused".to_string(),
+            Action::NextQualityPreset => "Next quality preset".to_string(),
+            Action::ToggleTimeJitter => "Toggle motion smear (supersampling)".to_string(),
+        }
+    }
+}
+
+// Key that an action is bound to
+#[derive(Debug, Clone, PartialEq)]
+pub enum BoundKey {
+    Named(NamedKey),
+    Character(String), // As produced by the keyboard layout, e.g., "a" or "["
+}
+
+impl BoundKey {
+    pub fn name(&self) -> String {
+        match self {
+            BoundKey::Named(named) => format!("{named:?}"),
+            BoundKey::Character(character) => character.clone(),
+        }
+    }
+
+    fn matches(&self, key: &Key<&str>) -> bool {
+        match (self, key) {
+            (BoundKey::Named(named), Key::Named(key_named)) => named == key_named,
+            (BoundKey::Character(character), Key::Character(key_character)) => {
+                character == key_character
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct KeyBindings {
+    bindings: Vec<(BoundKey, Action)>,
+}
+
+impl KeyBindings {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        // No keys bound
+        Self {
+            bindings: Vec::new(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn bind(&mut self, key: BoundKey, action: Action) {
+        // A key triggers one action, replace an earlier binding
+        self.unbind(&key);
+        self.bindings.push((key, action));
+    }
+
+    #[allow(dead_code)]
+    pub fn unbind(&mut self, key: &BoundKey) {
+        self.bindings.retain(|(bound_key, _)| bound_key != key);
+    }
+
+    pub fn bindings(&self) -> &[(BoundKey, Action)] {
+        // In the order they were bound
+        &self.bindings
+    }
+
+    pub fn action(&self, key: &Key<&str>) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(bound_key, _)| bound_key.matches(key))
+            .map(|&(_, action)| action)
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let character = |c: &str| BoundKey::Character(c.to_string());
+        let bindings = vec![
+            (BoundKey::Named(NamedKey::Escape), Action::Quit),
+            (character("q"), Action::Quit),
+            (BoundKey::Named(NamedKey::F11), Action::ToggleFullscreen),
+            (character("f"), Action::ToggleFullscreen),
+            (character("1"), Action::SetSpeed0(0.0)),
+            (character("2"), Action::SetSpeed0(0.5)),
+            (character("3"), Action::SetSpeed0(1.0)),
+            (character("4"), Action::SetSpeed0(1.5)),
+            (character("5"), Action::SetSpeed0(2.0)),
+            (character("6"), Action::SetSpeed1(-2.0)),
+            (character("7"), Action::SetSpeed1(-1.0)),
+            (character("8"), Action::SetSpeed1(0.0)),
+            (character("9"), Action::SetSpeed1(1.0)),
+            (character("0"), Action::SetSpeed1(2.0)),
+            (character("k"), Action::ToggleFreezeSpeed0),
+            (character("l"), Action::ToggleFreezeSpeed1),
+            (character("a"), Action::ToggleOption0),
+            (character("b"), Action::ToggleOption1),
+            (character("z"), Action::ToggleHiddenGroup(0)),
+            (character("x"), Action::ToggleHiddenGroup(1)),
+            (character("c"), Action::ToggleHiddenGroup(2)),
+            (character("v"), Action::ToggleHiddenGroup(3)),
+            (character("-"), Action::AdjustLightIntensity(-0.1)),
+            (character("="), Action::AdjustLightIntensity(0.1)),
+            (character("["), Action::DecreaseSupersampling),
+            (character("]"), Action::IncreaseSupersampling),
+            (BoundKey::Named(NamedKey::Tab), Action::NextScene),
+            (character("o"), Action::ToggleAutoFrame),
+            (character("g"), Action::ToggleLightGizmos),
+            (character("r"), Action::ToggleRecording),
+            (BoundKey::Named(NamedKey::F1), Action::ToggleHelp),
+            (character("h"), Action::ToggleHelp),
+            (character("p"), Action::TogglePowerSaver),
+            (character("u"), Action::NextQualityPreset),
+            (character("j"), Action::ToggleTimeJitter),
+        ];
+
+        Self { bindings }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character(c: &str) -> BoundKey {
+        BoundKey::Character(c.to_string())
+    }
+
+    #[test]
+    fn default_bindings_dispatch_actions() {
+        let bindings = KeyBindings::default();
+        assert_eq!(bindings.action(&Key::Character("q")), Some(Action::Quit));
+        assert_eq!(
+            bindings.action(&Key::Named(NamedKey::Escape)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            bindings.action(&Key::Character("3")),
+            Some(Action::SetSpeed0(1.0))
+        );
+        assert_eq!(bindings.action(&Key::Named(NamedKey::Space)), None);
+    }
+
+    #[test]
+    fn characters_and_named_keys_differ() {
+        // A character key named like a named key is not that key
+        let mut bindings = KeyBindings::new();
+        bindings.bind(character("Tab"), Action::NextScene);
+        assert_eq!(bindings.action(&Key::Named(NamedKey::Tab)), None);
+        assert_eq!(
+            bindings.action(&Key::Character("Tab")),
+            Some(Action::NextScene)
+        );
+    }
+
+    #[test]
+    fn rebinding_replaces_the_earlier_action() {
+        let mut bindings = KeyBindings::new();
+        bindings.bind(character("k"), Action::ToggleHelp);
+        bindings.bind(character("k"), Action::ToggleFullscreen);
+        assert_eq!(bindings.bindings().len(), 1);
+        assert_eq!(
+            bindings.action(&Key::Character("k")),
+            Some(Action::ToggleFullscreen)
+        );
+    }
+
+    #[test]
+    fn unbinding_removes_only_that_key() {
+        let mut bindings = KeyBindings::default();
+        let num_bindings = bindings.bindings().len();
+        bindings.unbind(&character("q"));
+        assert_eq!(bindings.bindings().len(), num_bindings - 1);
+        assert_eq!(bindings.action(&Key::Character("q")), None);
+        assert_eq!(
+            bindings.action(&Key::Named(NamedKey::Escape)),
+            Some(Action::Quit)
+        );
+    }
+}
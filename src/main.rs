@@ -1,128 +1,794 @@
 // juggler-in-rust - Drawing a simple raytraced scene in a resizable window
 // v0.2.0 2024-02-23
 
+mod config;
+mod fps_counter;
+mod key_bindings;
+mod materials;
+mod png;
+mod recorder;
 mod renderer;
 mod scene_juggler;
+mod scene_simple;
+mod scene_test;
+mod text;
+mod time_source;
+mod vec3;
+mod watermark;
 mod window;
 
 use std::fs::File;
-use std::time::Duration;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{io::Write, sync::Arc};
 
-use renderer::{Renderer, SceneOptions};
+use config::Config;
+use fps_counter::FPSCounter;
+use renderer::{
+    color_to_u32, gamma_encode, Camera, PopulateScene, Projection, Renderer, SceneOptions,
+};
+use time_source::{frame_time, TimeSource};
+use vecmath::Vector3;
+use watermark::Watermark;
 
 const WINDOW_TITLE: &str = "Juggler in Rust"; // Window title
 const TARGET_FPS: f64 = 24.0; // The best framerate, agreed by the world
 const FPS_TEST_ROUNDS: usize = 3; // Test render three times per resolution
+const BENCHMARK_ROUNDS: usize = 10; // Benchmark renders per resolution
+const BENCHMARK_SPAN_SIZES: [usize; 3] = [16, 64, 256]; // Pixels claimed at a time
+const FILE_RENDER_SIZE: usize = 720; // Width and height of rendered files
+const FILE_SUPERSAMPLE_FACTOR: usize = 2; // Render files larger and average down
+const FILE_MOTION_BLUR_SAMPLES: usize = 4; // Sub-frames averaged per rendered file
+const MAX_LOOP_FRAMES: usize = 100_000; // Longest seamless loop to look for
+const LOOP_TOLERANCE: f64 = 1e-6; // Allowed error in the number of cycles in a loop
+#[cfg(feature = "serde")]
+const MATERIALS_FILE: &str = "materials.json"; // Overrides built-in materials, if found
+
+// Render sizes to try, in increasing order
+const RENDER_SIZES: [usize; 15] = [
+    80, 128, 160, 200, 256, 320, 400, 480, 512, 640, 720, 800, 960, 1024, 1280,
+];
 
 fn main() {
+    // Diagnostics go to stderr, warnings and errors unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    // Report where render threads were in the image when they panic
+    renderer::install_panic_hook();
+
+    // Load materials before any scene is populated
+    #[cfg(feature = "serde")]
+    if Path::new(MATERIALS_FILE).exists() {
+        match materials::load_library(Path::new(MATERIALS_FILE)) {
+            Ok(()) => log::info!("Loaded materials from {MATERIALS_FILE}"),
+            Err(err) => log::warn!("Failed to load materials from {MATERIALS_FILE}: {err}"),
+        }
+    }
+
+    // Startup settings, defaults unless read from a file or given as arguments
+    let mut config = config::load();
+    let scene_index = config::apply_args(&mut config, std::env::args().skip(1)).and_then(|()| {
+        window::SCENES
+            .iter()
+            .position(|&(name, _)| name == config.scene)
+            .ok_or_else(|| format!("Unknown scene {}", config.scene))
+    });
+    let scene_index = match scene_index {
+        Ok(scene_index) => scene_index,
+        Err(err) => {
+            eprintln!("{err}\n\n{}", config::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let populate = window::SCENES[scene_index].1;
+
     // Create a raytracing renderer
     let renderer = renderer::Renderer::new();
+    configure_renderer(&renderer, &config, populate);
+    if let Some(num_threads) = config.num_threads {
+        renderer.set_num_threads(num_threads);
+    }
 
-    let to_files = false;
-    if to_files {
+    let to_files = config.to_files;
+    let file_size = config.render_size.unwrap_or(FILE_RENDER_SIZE);
+    let num_frames = config
+        .num_frames
+        .unwrap_or((config.target_fps * 15.0) as usize); // 15 seconds
+    let watermark = config
+        .watermark
+        .as_ref()
+        .and_then(|(path, corner, opacity)| {
+            // Rendering goes on without the logo if it cannot be loaded
+            Watermark::load(path, *corner, *opacity, config.watermark_margin)
+                .map_err(|err| log::warn!("Cannot load watermark {}: {err}", path.display()))
+                .ok()
+        });
+    let seamless_loop = config.seamless_loop && {
+        // Only the juggler's cycles are known
+        let is_juggler = window::SCENES[scene_index].0 == "juggler";
+        if !is_juggler {
+            log::warn!("Looping is only supported for the juggler scene, ignored");
+        }
+        is_juggler
+    };
+    let file_settings = FileSettings {
+        size: file_size,
+        output_size: config.output_size.unwrap_or(file_size),
+        num_frames: if seamless_loop {
+            seamless_num_frames(config.target_fps, num_frames)
+        } else {
+            num_frames
+        },
+        fps: config.target_fps,
+        out_dir: &config.output_dir,
+        format: config.output_format,
+        motion_blur_samples: FILE_MOTION_BLUR_SAMPLES,
+        export_depth: config.export_depth,
+        watermark: watermark.as_ref(),
+    };
+    if config.benchmark {
+        // Print render performance for all sizes, no window needed
+        run_benchmark(&renderer);
+    } else if let Some(secs) = config.dump_scene {
+        // Print the scene at the given time, for debugging
+        print_scene(&renderer, Duration::from_secs_f64(secs));
+    } else if config.turntable {
+        // Orbit the juggler once, ignoring the scene's camera
+        let num_frames = config
+            .num_frames
+            .unwrap_or((config.target_fps * 10.0) as usize); // 10 seconds
+        render_turntable(
+            &renderer,
+            file_size,
+            12.0,
+            0.3,
+            [0.0, 3.0, 0.0],
+            num_frames,
+            config.target_fps,
+            &config.output_dir,
+            config.output_format,
+        );
+    } else if to_files && config.parallel_frames {
+        // Render several frames at a time, each frame on a single thread
+        render_animation(&config, populate, &file_settings);
+    } else if to_files {
         // Render to files instead of displaying on-screen
-        render_to_files(&renderer);
+        render_to_files(&renderer, &file_settings);
     } else {
-        // Select render size according to the desired frame rate
-        find_optimal_render_size(&renderer);
+        // Select render size according to the desired frame rate, unless given
+        match config.render_size {
+            Some(size) => renderer.set_size((size, size)),
+            None => find_optimal_render_size(&renderer, config.target_fps),
+        }
+
+        // Create a window, or more to compare settings side by side. Each
+        // window has a renderer of its own, all rendering the same size.
+        let num_windows = config.num_windows.max(1);
+        let mut windows = Vec::with_capacity(num_windows);
+        for n in 0..num_windows {
+            let window_renderer = if n == 0 {
+                renderer.clone()
+            } else {
+                let window_renderer = Renderer::new();
+                window_renderer.set_size(renderer.get_size());
+                window_renderer.set_supersampling(config.samples_per_axis);
+                window_renderer.set_gamma(config.gamma);
+                if let Some(num_threads) = config.num_threads {
+                    window_renderer.set_num_threads(num_threads);
+                }
+
+                // View the juggler from above and behind
+                let mut camera = Camera {
+                    pos: [0.0, 12.0, 10.0],
+                    right: [1.0, 0.0, 0.0],
+                    up: [0.0, 1.0, 0.0],
+                    forward: [0.0, 0.0, 1.0],
+                    projection: Projection::Perspective,
+                };
+                camera.look_at([0.0, 3.0, 0.0]);
+                window_renderer.set_camera(Some(camera));
 
-        // Create a window
-        let mut window = window::Window::new(&renderer);
+                window_renderer
+            };
 
-        window.set_title(&format!("{WINDOW_TITLE}"));
+            let mut window = window::Window::new(&window_renderer);
+            window.set_title(&format!("{WINDOW_TITLE}"));
+            window.set_scene_index(scene_index);
+            window.set_scaling(config.scaling);
+            if config.fixed_step {
+                // Same scene times as rendered files, however long frames take
+                window.set_time_source(TimeSource::fixed_step(config.target_fps));
+            }
+            windows.push(window);
+        }
 
         // Run event loop
-        window.run();
+        window::run_windows(&windows);
 
         // This part is not reached on all platforms
     }
 }
 
-fn find_optimal_render_size(renderer: &Arc<Renderer>) {
-    let try_sizes = [
-        80, 128, 160, 200, 256, 320, 400, 480, 512, 640, 720, 800, 960, 1024, 1280,
-    ];
+struct BenchmarkResult {
+    mean_duration: Duration, // Average render time of one frame
+    fps: f64,
+    rays: u64,         // Average number of rays per frame
+    sphere_tests: u64, // Average number of ray-sphere tests per frame
+}
 
-    // Default scene options
-    let scene_options = SceneOptions {
-        speed_0: 1.0,
-        speed_1: 1.0,
-        option_0: false,
-        option_1: false,
-    };
+fn benchmark_size(
+    renderer: &Arc<Renderer>,
+    size: usize,
+    rounds: usize,
+    scene_options: &SceneOptions,
+) -> BenchmarkResult {
+    // Do a few test renders with the given size, without first-touch costs
+    renderer.set_size((size, size));
+    renderer.warmup();
 
-    for n in 1..try_sizes.len() {
-        let size = try_sizes[n];
+    let mut fps_counter = FPSCounter::new();
+    let (mut total_rays, mut total_sphere_tests) = (0, 0);
+    for _ in 0..rounds {
+        renderer.start_render(Duration::ZERO, scene_options);
+        renderer.wait_for_completion(false);
+        fps_counter.new_frame(renderer.get_duration());
+        let stats = renderer.get_stats();
+        total_rays += stats.rays;
+        total_sphere_tests += stats.sphere_tests;
+    }
 
-        // Do a few test renders with the given size
-        renderer.set_size((size, size));
+    // Calculate FPS
+    let mean_duration = fps_counter.average().unwrap_or(Duration::ZERO);
+    BenchmarkResult {
+        mean_duration,
+        fps: 1.0 / mean_duration.as_secs_f64(),
+        rays: total_rays / rounds.max(1) as u64,
+        sphere_tests: total_sphere_tests / rounds.max(1) as u64,
+    }
+}
 
-        let mut total_duration = Duration::ZERO;
-        for _ in 0..FPS_TEST_ROUNDS {
-            renderer.start_render(Duration::ZERO, &scene_options);
-            renderer.wait_for_completion(false);
-            total_duration += renderer.get_duration();
-        }
+fn find_optimal_render_size(renderer: &Arc<Renderer>, target_fps: f64) {
+    let scene_options = SceneOptions::default();
 
-        // Calculate FPS
-        let fps = 1.0 / (total_duration.as_secs_f64() / FPS_TEST_ROUNDS as f64);
-        if fps < TARGET_FPS {
+    for n in 1..RENDER_SIZES.len() {
+        let result = benchmark_size(renderer, RENDER_SIZES[n], FPS_TEST_ROUNDS, &scene_options);
+        log::debug!("Render size {}: {:.1} fps", RENDER_SIZES[n], result.fps);
+        if result.fps < target_fps {
             // FPS is lower than target, use the previous size
-            let size = try_sizes[n - 1];
+            let size = RENDER_SIZES[n - 1];
             renderer.set_size((size, size));
-            break;
+            log::info!("Selected render size {size}x{size} for {target_fps} fps");
+            return;
         }
     }
+
+    // Fast enough at every size, keep the largest
+    let size = RENDER_SIZES[RENDER_SIZES.len() - 1];
+    log::info!("Selected the largest render size {size}x{size}");
 }
 
-fn render_to_files(renderer: &Arc<Renderer>) {
-    // Render to files in a high resolution
-    let size = 720;
-    renderer.set_size((size, size));
+fn print_scene(renderer: &Arc<Renderer>, duration: Duration) {
+    let scene_options = SceneOptions::default();
 
-    // Default scene options
-    let scene_options = SceneOptions {
-        speed_0: 1.0,
-        speed_1: 1.0,
-        option_0: false,
-        option_1: false,
-    };
+    // The scene is generated when rendering, so render a tiny image
+    renderer.set_size((RENDER_SIZES[0], RENDER_SIZES[0]));
+    renderer.start_render(duration, &scene_options);
+    renderer.wait_for_completion(false);
 
-    let ppm_header = format!("P6\n{size} {size}\n255\n");
+    print!("{}", renderer.get_scene().describe());
+}
 
-    let fps = TARGET_FPS;
-    let num_frames = (fps * 15.0) as usize; // 15 seconds
+fn run_benchmark(renderer: &Arc<Renderer>) {
+    let scene_options = SceneOptions::default();
 
-    for frame in 0..num_frames {
-        let secs = frame as f64 / fps;
-        println!("Frame {frame} @ {secs:.3} s");
+    // Print results as CSV
+    println!("size,span_size,mean_ms,fps,rays,sphere_tests");
+    for size in RENDER_SIZES {
+        for span_size in BENCHMARK_SPAN_SIZES {
+            renderer.set_span_size(span_size);
+            let result = benchmark_size(renderer, size, BENCHMARK_ROUNDS, &scene_options);
+            println!(
+                "{},{},{:.3},{:.2},{},{}",
+                size,
+                span_size,
+                result.mean_duration.as_secs_f64() * 1000.0,
+                result.fps,
+                result.rays,
+                result.sphere_tests
+            );
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+enum ImageFormat {
+    Ppm, // Portable Pixmap
+    Bmp, // Windows bitmap, 24 bits per pixel
+    Png, // Portable Network Graphics, with alpha
+}
+
+impl ImageFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+fn loop_num_frames(fps: f64, scene_options: &SceneOptions) -> Option<usize> {
+    // Fewest frames after which the juggler's bounce and camera cycles both
+    // restart, so that the frame after the last would equal the first.
+    // A cycle stopped by zero speed fits any number of frames.
+    let cycle_lengths: Vec<f64> = [
+        (scene_juggler::BOUNCE_CYCLE_S, scene_options.speed_0),
+        (scene_juggler::CAMERA_CYCLE_S, scene_options.speed_1),
+    ]
+    .into_iter()
+    .filter(|&(_, speed)| speed != 0.0)
+    .map(|(cycle_s, speed)| cycle_s / speed.abs())
+    .collect();
+
+    (1..=MAX_LOOP_FRAMES).find(|&frames| {
+        let secs = frames as f64 / fps;
+        cycle_lengths.iter().all(|&cycle_s| {
+            let cycles = secs / cycle_s;
+            cycles.round() >= 1.0 && (cycles - cycles.round()).abs() < LOOP_TOLERANCE
+        })
+    })
+}
+
+// What rendering to files writes, the same whether frames render one by one
+// or several at a time
+struct FileSettings<'a> {
+    size: usize,        // Render size, before supersampling
+    output_size: usize, // Size of written files, scaled from size if different
+    num_frames: usize,
+    fps: f64,
+    out_dir: &'a Path,
+    format: ImageFormat,
+    motion_blur_samples: usize,
+    export_depth: bool,               // Also write a depth file per frame
+    watermark: Option<&'a Watermark>, // Drawn over each file
+}
+
+fn configure_renderer(renderer: &Arc<Renderer>, config: &Config, populate: PopulateScene) {
+    // Settings that change the rendered pixels, shared by every renderer
+    // that writes files
+    renderer.set_scene(populate);
+    renderer.set_supersampling(config.samples_per_axis);
+    renderer.set_artificial_delay(config.span_delay_ms.map(Duration::from_millis));
+    renderer.set_transparent_background(config.transparent_background);
+    renderer.set_gamma(config.gamma);
+}
+
+fn seamless_num_frames(fps: f64, num_frames: usize) -> usize {
+    // Shortest seamless loop at normal speed, or num_frames if there is none
+    match loop_num_frames(fps, &SceneOptions::default()) {
+        Some(loop_frames) => {
+            log::info!("Rendering a seamless loop of {loop_frames} frames");
+            loop_frames
+        }
+        None => {
+            log::warn!("No seamless loop within {MAX_LOOP_FRAMES} frames, not looping");
+            num_frames
+        }
+    }
+}
+
+fn render_to_files(renderer: &Arc<Renderer>, settings: &FileSettings) {
+    // Render to files, usually in a high resolution
+    // Render larger and average down, for antialiased edges
+    let render_size = settings.size * FILE_SUPERSAMPLE_FACTOR;
+    renderer.set_size((render_size, render_size));
+
+    // Collect render time statistics
+    let mut fps_counter = FPSCounter::new();
+
+    for frame in 0..settings.num_frames {
+        let duration = frame_time(frame, settings.fps);
+        log::info!("Frame {frame} @ {:.3} s", duration.as_secs_f64());
+        render_file(renderer, frame, settings, &mut fps_counter);
+    }
 
-        // Render image
-        let duration = Duration::from_secs_f64(secs);
-        renderer.start_render(duration, &scene_options);
+    // Print render time statistics over the most recent frames
+    if let (Some(average), Some(min), Some(max), Some(p95)) = (
+        fps_counter.average(),
+        fps_counter.min(),
+        fps_counter.max(),
+        fps_counter.percentile(95.0),
+    ) {
+        log::info!(
+            "Frame time: avg {:.3} s, min {:.3} s, max {:.3} s, 95th percentile {:.3} s",
+            average.as_secs_f64(),
+            min.as_secs_f64(),
+            max.as_secs_f64(),
+            p95.as_secs_f64()
+        );
+    }
+}
+
+fn render_file(
+    renderer: &Arc<Renderer>,
+    frame: usize,
+    settings: &FileSettings,
+    fps_counter: &mut FPSCounter,
+) {
+    // Render one frame and write its files. The renderer is already sized
+    // for supersampling.
+    let scene_options = SceneOptions::default();
+    let (size, output_size, fps) = (settings.size, settings.output_size, settings.fps);
+
+    // Same scene times as a window with a fixed-step time source
+    let duration = frame_time(frame, fps);
+
+    // Sum of sub-frames in linear colors, for motion blur
+    let motion_blur_samples = settings.motion_blur_samples.max(1);
+    let render_pixels = (size * FILE_SUPERSAMPLE_FACTOR).pow(2);
+    let mut accumulated = vec![(0.0, 0.0, 0.0); render_pixels];
+    let mut accumulated_alpha = vec![0.0; render_pixels];
+
+    // Render sub-frames spread over the frame's time interval
+    for sample in 0..motion_blur_samples {
+        let offset = Duration::from_secs_f64(sample as f64 / motion_blur_samples as f64 / fps);
+        renderer.start_render(duration + offset, &scene_options);
         renderer.wait_for_completion(false);
+        fps_counter.new_frame(renderer.get_duration());
+
+        if settings.export_depth && sample == 0 {
+            // Depth at the frame's own time, not blurred
+            let depth = renderer.depth_pass((output_size, output_size));
+            let filename = format!("depth{frame:03}.pfm");
+            write_depth(&settings.out_dir.join(filename), &depth, output_size);
+        }
 
-        // Write image to a Portable Pixmap (PPM) file
+        let hdr_buffer = renderer.get_hdr_buffer();
+        for (sum, color) in accumulated
+            .iter_mut()
+            .zip(hdr_buffer.lock().unwrap().iter())
         {
-            let filename = format!("img{:03}.ppm", frame);
-            let mut file = File::create(filename).unwrap();
+            sum.0 += color.0 / motion_blur_samples as f64;
+            sum.1 += color.1 / motion_blur_samples as f64;
+            sum.2 += color.2 / motion_blur_samples as f64;
+        }
+        for (sum, alpha) in accumulated_alpha
+            .iter_mut()
+            .zip(alpha_of(&renderer.snapshot()))
+        {
+            *sum += alpha / motion_blur_samples as f64;
+        }
+    }
+
+    // Write image file, with pixel data averaged in linear colors
+    // before quantization
+    let buffer = downsample(
+        &accumulated,
+        &accumulated_alpha,
+        size,
+        FILE_SUPERSAMPLE_FACTOR,
+        renderer.get_gamma(),
+    );
+    let mut buffer = scale_image(&buffer, size, output_size);
+    if let Some(watermark) = settings.watermark {
+        watermark.draw(&mut buffer, output_size, output_size);
+    }
+    let filename = format!("img{:03}.{}", frame, settings.format.extension());
+    write_image(
+        &settings.out_dir.join(filename),
+        &buffer,
+        output_size,
+        settings.format,
+    );
+}
+
+fn render_animation(config: &Config, populate: PopulateScene, settings: &FileSettings) {
+    // Render frames concurrently, each worker rendering one frame at a time
+    // on a single thread with a renderer of its own. Faster than rendering
+    // frames one by one on many cores, where threads wait for each other.
+    // The files are the same as from render_to_files.
+    let num_frames = settings.num_frames;
+    let render_size = settings.size * FILE_SUPERSAMPLE_FACTOR;
+
+    let next_frame = AtomicUsize::new(0);
+    let frames_done = AtomicUsize::new(0);
+    let start_time = Instant::now();
+
+    thread::scope(|scope| {
+        for _ in 0..num_cpus::get().min(num_frames) {
+            scope.spawn(|| {
+                // Each worker owns its render buffers
+                let renderer = Renderer::new();
+                configure_renderer(&renderer, config, populate);
+                renderer.set_num_threads(1);
+                renderer.set_size((render_size, render_size));
+                let mut fps_counter = FPSCounter::new();
+
+                loop {
+                    let frame = next_frame.fetch_add(1, Ordering::Relaxed);
+                    if frame >= num_frames {
+                        break;
+                    }
+
+                    // Write files as soon as the frame is done
+                    render_file(&renderer, frame, settings, &mut fps_counter);
+
+                    // Report overall progress, frames may complete out of order
+                    let done = frames_done.fetch_add(1, Ordering::Relaxed) + 1;
+                    log::info!(
+                        "Frame {frame} done, {done}/{num_frames} @ {:.1} s",
+                        start_time.elapsed().as_secs_f64()
+                    );
+                }
+            });
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_turntable(
+    renderer: &Arc<Renderer>,
+    size: usize, // Size of written files, rendered larger and averaged down
+    radius: f64,
+    elevation: f64, // Radians above the horizontal plane of look_at
+    look_at: Vector3<f64>,
+    num_frames: usize,
+    fps: f64,
+    out_dir: &Path,
+    format: ImageFormat,
+) {
+    // Render one full turn around look_at, from a camera that overrides the
+    // scene's own. The scene itself animates as usual.
+    let supersample_factor = FILE_SUPERSAMPLE_FACTOR;
+    renderer.set_size((size * supersample_factor, size * supersample_factor));
 
+    let scene_options = SceneOptions::default();
+
+    for frame in 0..num_frames {
+        let azimuth = frame as f64 / num_frames as f64 * std::f64::consts::TAU;
+        let mut camera = Camera {
+            pos: [
+                look_at[0] + radius * elevation.cos() * azimuth.sin(),
+                look_at[1] + radius * elevation.sin(),
+                look_at[2] - radius * elevation.cos() * azimuth.cos(),
+            ],
+            right: [1.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            forward: [0.0, 0.0, 1.0],
+            projection: Projection::Perspective,
+        };
+        camera.look_at(look_at);
+        renderer.set_camera(Some(camera));
+
+        renderer.start_render(frame_time(frame, fps), &scene_options);
+        renderer.wait_for_completion(false);
+
+        let hdr_buffer = renderer.get_hdr_buffer();
+        let alpha_buffer = alpha_of(&renderer.snapshot());
+        let buffer = downsample(
+            &hdr_buffer.lock().unwrap(),
+            &alpha_buffer,
+            size,
+            supersample_factor,
+            renderer.get_gamma(),
+        );
+        let filename = format!("turntable{:03}.{}", frame, format.extension());
+        write_image(&out_dir.join(filename), &buffer, size, format);
+        log::info!("Turntable frame {frame}/{num_frames} done");
+    }
+
+    // Back to the scene's camera
+    renderer.set_camera(None);
+}
+
+fn write_image(path: &Path, buffer: &[u32], size: usize, format: ImageFormat) {
+    let mut file = File::create(path).unwrap();
+
+    match format {
+        ImageFormat::Ppm => {
             // Write header
+            let ppm_header = format!("P6\n{size} {size}\n255\n");
             file.write_all(ppm_header.as_bytes()).unwrap();
 
-            // Write pixel data
-            let render_buffer = renderer.get_buffer();
-            let buffer = render_buffer.lock().unwrap();
-            for offset in 0..(size * size) {
-                let pixel = buffer[offset];
-                let pixel_conv = [
+            // Write pixel data, top row first
+            let mut data = Vec::with_capacity(size * size * 3);
+            for &pixel in buffer {
+                data.extend_from_slice(&[
                     (pixel >> 16 & 0xff) as u8, // R
                     (pixel >> 8 & 0xff) as u8,  // G
-                    (pixel >> 0 & 0xff) as u8,  // B
-                ];
-                file.write_all(&pixel_conv).unwrap();
+                    (pixel & 0xff) as u8,       // B
+                ]);
+            }
+            file.write_all(&data).unwrap();
+        }
+        ImageFormat::Bmp => {
+            // Rows are padded to a multiple of four bytes
+            let row_size = (size * 3).div_ceil(4) * 4;
+            let data_size = (row_size * size) as u32;
+            let header_size = 14 + 40;
+
+            // Write file header and BITMAPINFOHEADER
+            let mut header = Vec::with_capacity(header_size);
+            header.extend_from_slice(b"BM");
+            header.extend_from_slice(&(header_size as u32 + data_size).to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+            header.extend_from_slice(&(header_size as u32).to_le_bytes()); // Pixel data offset
+            header.extend_from_slice(&40u32.to_le_bytes()); // Info header size
+            header.extend_from_slice(&(size as i32).to_le_bytes()); // Width
+            header.extend_from_slice(&(size as i32).to_le_bytes()); // Height, bottom-up
+            header.extend_from_slice(&1u16.to_le_bytes()); // Planes
+            header.extend_from_slice(&24u16.to_le_bytes()); // Bits per pixel
+            header.extend_from_slice(&0u32.to_le_bytes()); // No compression
+            header.extend_from_slice(&data_size.to_le_bytes());
+            header.extend_from_slice(&[0; 16]); // Resolution and palette, unused
+            file.write_all(&header).unwrap();
+
+            // Write pixel data, bottom row first
+            let mut data = Vec::with_capacity(data_size as usize);
+            for row in buffer.chunks(size).rev() {
+                for &pixel in row {
+                    data.extend_from_slice(&[
+                        (pixel & 0xff) as u8,       // B
+                        (pixel >> 8 & 0xff) as u8,  // G
+                        (pixel >> 16 & 0xff) as u8, // R
+                    ]);
+                }
+                data.resize(data.len() + row_size - size * 3, 0);
             }
+            file.write_all(&data).unwrap();
+        }
+        ImageFormat::Png => {
+            // RGBA, transparent where the background shows
+            file.write_all(&png::encode_rgba(buffer, size, size))
+                .unwrap();
+        }
+    }
+}
+
+fn write_depth(path: &Path, depth: &[f32], size: usize) {
+    // Grayscale PFM, little-endian (negative scale) 32-bit floats, bottom
+    // row first
+    let mut file = File::create(path).unwrap();
+    let pfm_header = format!("Pf\n{size} {size}\n-1.0\n");
+    file.write_all(pfm_header.as_bytes()).unwrap();
+
+    let mut data = Vec::with_capacity(size * size * 4);
+    for row in depth.chunks(size).rev() {
+        for &distance in row {
+            data.extend_from_slice(&distance.to_le_bytes());
+        }
+    }
+    file.write_all(&data).unwrap();
+}
+
+fn downsample(
+    hdr_buffer: &[(f64, f64, f64)],
+    alpha_buffer: &[f64],
+    size: usize,
+    factor: usize,
+    gamma: f64,
+) -> Vec<u32> {
+    // Box filter factor x factor blocks of a (size * factor)^2 buffer into
+    // size^2, averaging linear colors before gamma encoding
+    let source_width = size * factor;
+    let num_samples = (factor * factor) as f64;
+    let mut buffer = Vec::with_capacity(size * size);
+
+    for y in 0..size {
+        for x in 0..size {
+            let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+            for source_y in (y * factor)..((y + 1) * factor) {
+                for source_x in (x * factor)..((x + 1) * factor) {
+                    let color = hdr_buffer[source_y * source_width + source_x];
+                    r += color.0;
+                    g += color.1;
+                    b += color.2;
+                    a += alpha_buffer[source_y * source_width + source_x];
+                }
+            }
+            let average = (r / num_samples, g / num_samples, b / num_samples);
+            let color = color_to_u32(gamma_encode(average, gamma));
+            let alpha = (255.0 * a / num_samples).round() as u32;
+            buffer.push(color & 0x00ff_ffff | alpha << 24);
+        }
+    }
+
+    buffer
+}
+
+fn scale_image(buffer: &[u32], size: usize, output_size: usize) -> Vec<u32> {
+    // Square image to another size, box filtered when shrinking and nearest
+    // neighbor when growing. Channels, alpha included, are averaged as is.
+    if output_size == size {
+        return buffer.to_vec();
+    }
+
+    let mut output = Vec::with_capacity(output_size * output_size);
+    for y in 0..output_size {
+        // Source pixels covered by this output pixel, at least one
+        let y0 = y * size / output_size;
+        let y1 = ((y + 1) * size / output_size).max(y0 + 1);
+        for x in 0..output_size {
+            let x0 = x * size / output_size;
+            let x1 = ((x + 1) * size / output_size).max(x0 + 1);
+
+            let mut sums = [0u32; 4];
+            for source_y in y0..y1 {
+                for source_x in x0..x1 {
+                    let pixel = buffer[source_y * size + source_x];
+                    for (channel, sum) in sums.iter_mut().enumerate() {
+                        *sum += pixel >> (8 * channel) & 0xff;
+                    }
+                }
+            }
+
+            let count = ((y1 - y0) * (x1 - x0)) as u32;
+            let pixel = sums.iter().enumerate().fold(0, |pixel, (channel, sum)| {
+                pixel | ((sum + count / 2) / count) << (8 * channel)
+            });
+            output.push(pixel);
+        }
+    }
+
+    output
+}
+
+fn alpha_of(buffer: &[u32]) -> Vec<f64> {
+    // Alpha from the high byte of rendered pixels, for downsample()
+    buffer
+        .iter()
+        .map(|&pixel| (pixel >> 24) as f64 / 255.0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_frames_write_the_same_files() {
+        // Frame workers take the same settings as rendering frames one by one
+        let config = Config {
+            samples_per_axis: 2,
+            gamma: 2.2,
+            transparent_background: true,
+            ..Config::default()
+        };
+        let dirs = ["sequential", "parallel"].map(|name| {
+            let dir = std::env::temp_dir().join(format!("juggler-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        });
+        let settings = |out_dir| FileSettings {
+            size: 12,
+            output_size: 16,
+            num_frames: 2,
+            fps: TARGET_FPS,
+            out_dir,
+            format: ImageFormat::Png,
+            motion_blur_samples: 2,
+            export_depth: true,
+            watermark: None,
+        };
+
+        let renderer = Renderer::new();
+        configure_renderer(&renderer, &config, scene_juggler::populate_scene);
+        render_to_files(&renderer, &settings(&dirs[0]));
+        render_animation(&config, scene_juggler::populate_scene, &settings(&dirs[1]));
+
+        for filename in ["img001.png", "depth001.pfm"] {
+            let [sequential, parallel] = dirs
+                .each_ref()
+                .map(|dir| std::fs::read(dir.join(filename)).unwrap());
+            assert!(sequential == parallel, "{filename} differs");
+        }
+        for dir in dirs {
+            std::fs::remove_dir_all(dir).unwrap();
         }
     }
 }
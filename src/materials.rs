@@ -0,0 +1,97 @@
+// materials.rs - Named materials shared by the scenes, optionally loaded
+// from a JSON file
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::renderer::{
+    Material,
+    Texture::{CheckerXZ, Color},
+};
+
+pub type Materials = HashMap<String, Material>;
+
+static LIBRARY: OnceLock<Materials> = OnceLock::new();
+
+pub fn library() -> &'static Materials {
+    // Built-in materials, unless a library was loaded before first use
+    LIBRARY.get_or_init(default_materials)
+}
+
+#[cfg(feature = "serde")]
+pub fn load_library(path: &std::path::Path) -> std::io::Result<()> {
+    // Loaded materials replace built-in ones of the same name
+    let text = std::fs::read_to_string(path)?;
+    let loaded: Materials = serde_json::from_str(&text)?;
+    let mut materials = default_materials();
+    materials.extend(loaded);
+
+    LIBRARY.set(materials).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "Material library already in use",
+        )
+    })
+}
+
+fn default_materials() -> Materials {
+    let shiny = |texture, reflective| Material {
+        texture,
+        specular: 100.0,           // Shiny
+        reflective,                // Proportion of reflected color
+        metallic: 0.0,             // Dielectric, reflections not tinted
+        skip_lighting: false,      // Regular lighting calculations
+        one_sided: false,          // Normal faces the incoming ray
+        opacity: 1.0,              // Fully opaque
+        shadow_catcher: false,     // Visible surface
+        emission: (0.0, 0.0, 0.0), // No glow
+        ripple: None,              // Smooth
+    };
+
+    let materials = [
+        (
+            "ground",
+            Material {
+                texture: CheckerXZ {
+                    color1: (1.0, 1.0, 0.0), // Yellow
+                    color2: (0.0, 1.0, 0.0), // Green
+                    scale: 4.0,
+                    distance_scaling: false,
+                }, // Yellow-green checkered texture
+                specular: -1.0,            // Dull, not shiny
+                reflective: 0.0,           // Not reflective
+                metallic: 0.0,             // Dielectric
+                skip_lighting: false,      // Regular lighting calculations
+                one_sided: false,          // Normal faces the incoming ray
+                opacity: 1.0,              // Fully opaque
+                shadow_catcher: false,     // Visible surface
+                emission: (0.0, 0.0, 0.0), // No glow
+                ripple: None,              // Smooth
+            },
+        ),
+        (
+            "juggling_ball",
+            Material {
+                metallic: 1.0, // Chrome
+                ..shiny(Color(0.9, 0.9, 0.9), 0.8)
+            },
+        ),
+        (
+            "gold_ball",
+            Material {
+                metallic: 1.0, // Gold
+                ..shiny(Color(1.0, 0.77, 0.34), 0.8)
+            },
+        ),
+        ("body", shiny(Color(1.0, 0.1, 0.1), 0.0)), // Red
+        ("extra_body", shiny(Color(1.0, 0.1, 0.1), 0.3)), // Red, a little reflective
+        ("skin", shiny(Color(1.0, 0.7, 0.7), 0.0)), // Pink
+        ("hair", shiny(Color(0.2, 0.1, 0.1), 0.0)), // Very dark brown
+        ("eye", shiny(Color(0.1, 0.1, 1.0), 0.0)),  // Blue
+    ];
+
+    materials
+        .into_iter()
+        .map(|(name, material)| (name.to_string(), material))
+        .collect()
+}
@@ -0,0 +1,557 @@
+// png.rs - Minimal PNG encoder for RGBA images with alpha, std only. Pixel
+// data is stored uncompressed, which keeps the encoder small at the cost
+// of larger files. The decoder reads the common 8-bit PNG files, e.g.,
+// logos, with a small inflate implementation, and checks CRCs and Adler-32.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const MAX_STORED_BLOCK: usize = 65535; // Deflate limit for an uncompressed block
+const MAX_DECODED_PIXELS: usize = 1 << 26; // Larger images are refused, not a logo
+
+// Deflate length and distance codes: base values and extra bits
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order in which code length code lengths are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub fn encode_rgba(buffer: &[u32], width: usize, height: usize) -> Vec<u8> {
+    // Buffer pixels are 0xAARRGGBB with colors premultiplied by alpha, PNG
+    // wants straight (not premultiplied) colors
+    let mut raw = Vec::with_capacity(height * (1 + width * 4));
+    for row in buffer.chunks(width).take(height) {
+        raw.push(0); // Filter type: none
+        for &pixel in row {
+            let alpha = pixel >> 24;
+            let straight = |channel: u32| {
+                // Fully transparent pixels have no color
+                (channel * 255 + alpha / 2)
+                    .checked_div(alpha)
+                    .map_or(0, |channel| channel.min(255) as u8)
+            };
+            raw.extend_from_slice(&[
+                straight(pixel >> 16 & 0xff), // R
+                straight(pixel >> 8 & 0xff),  // G
+                straight(pixel & 0xff),       // B
+                alpha as u8,                  // A
+            ]);
+        }
+    }
+
+    // Image header: 8 bits per channel, color type 6 (RGBA), no interlacing
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = Vec::with_capacity(raw.len() + raw.len() / MAX_STORED_BLOCK * 5 + 64);
+    png.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    // Length, type, data and CRC of type and data
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Zlib stream of uncompressed deflate blocks
+    let mut zlib = Vec::with_capacity(data.len() + data.len() / MAX_STORED_BLOCK * 5 + 11);
+    zlib.extend_from_slice(&[0x78, 0x01]); // Deflate, 32 kB window, no dictionary
+
+    // At least one block, even if empty
+    let num_blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let blocks = data
+        .chunks(MAX_STORED_BLOCK)
+        .chain(data.is_empty().then_some(data));
+    for (n, block) in blocks.enumerate() {
+        let last = n + 1 == num_blocks;
+        zlib.push(last as u8); // BFINAL, BTYPE 00: stored
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    // CRC-32 (IEEE), bit by bit, fast enough for a frame now and then
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        // Largest chunk that cannot overflow before the modulo
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    b << 16 | a
+}
+
+pub fn decode_rgba(png: &[u8]) -> Result<(Vec<u32>, usize, usize), String> {
+    // Pixels as 0xAARRGGBB with colors premultiplied by alpha, as the render
+    // buffer, and width and height. Reads 8-bit gray, gray with alpha, RGB,
+    // RGBA and palette images, without interlacing.
+    if !png.starts_with(&SIGNATURE) {
+        return Err("Not a PNG file".to_string());
+    }
+
+    let mut header = None;
+    let (mut palette, mut transparency, mut compressed) = (&[][..], &[][..], Vec::new());
+    let mut rest = &png[SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        // Length, type, data and CRC of type and data
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let (data, crc) = rest
+            .get(8..8 + length)
+            .zip(rest.get(8 + length..12 + length))
+            .ok_or("Truncated PNG chunk".to_string())?;
+        if crc32(&rest[4..8 + length]) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Err("Corrupt PNG chunk, its CRC differs".to_string());
+        }
+        match &rest[4..8] {
+            b"IHDR" if length >= 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {} // Ancillary chunks, e.g., gamma, are ignored
+        }
+        rest = rest.get(12 + length..).unwrap_or(&[]);
+    }
+
+    let header = header.ok_or("Missing PNG header".to_string())?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (bit_depth, color_type, interlace) = (header[8], header[9], header[12]);
+    let channels = match color_type {
+        0 => 1, // Gray
+        2 => 3, // RGB
+        3 => 1, // Palette index
+        4 => 2, // Gray and alpha
+        6 => 4, // RGBA
+        _ => return Err(format!("Unknown PNG color type {color_type}")),
+    };
+    if bit_depth != 8 || interlace != 0 {
+        return Err(format!(
+            "Unsupported PNG: {bit_depth} bits per channel, interlace {interlace}"
+        ));
+    }
+    if width == 0 || height == 0 || width.saturating_mul(height) > MAX_DECODED_PIXELS {
+        return Err(format!("Unsupported PNG size {width}x{height}"));
+    }
+
+    // Zlib stream: two header bytes, deflate data, Adler-32 of the data
+    if compressed.len() < 6 || compressed[0] & 0x0f != 8 {
+        return Err("PNG data is not deflate compressed".to_string());
+    }
+    let (deflate, checksum) = compressed[2..].split_at(compressed.len() - 6);
+    let stride = width * channels;
+    let mut raw = inflate(deflate, height * (1 + stride))?;
+    if raw.len() < height * (1 + stride) {
+        return Err("Truncated PNG data".to_string());
+    }
+    if adler32(&raw) != u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+        return Err("Corrupt PNG data, its Adler-32 differs".to_string());
+    }
+    unfilter(&mut raw, stride, channels, height)?;
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in raw.chunks(1 + stride).take(height) {
+        for sample in row[1..].chunks(channels) {
+            let (r, g, b, a) = match color_type {
+                0 => (sample[0], sample[0], sample[0], 255),
+                2 => (sample[0], sample[1], sample[2], 255),
+                3 => {
+                    let index = sample[0] as usize;
+                    let color = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or("PNG palette index out of range".to_string())?;
+                    let alpha = transparency.get(index).copied().unwrap_or(255);
+                    (color[0], color[1], color[2], alpha)
+                }
+                4 => (sample[0], sample[0], sample[0], sample[1]),
+                _ => (sample[0], sample[1], sample[2], sample[3]),
+            };
+            let premultiplied = |channel: u8| (channel as u32 * a as u32 + 127) / 255;
+            pixels.push(
+                (a as u32) << 24
+                    | premultiplied(r) << 16
+                    | premultiplied(g) << 8
+                    | premultiplied(b),
+            );
+        }
+    }
+
+    Ok((pixels, width, height))
+}
+
+fn unfilter(
+    raw: &mut [u8],
+    stride: usize,
+    bytes_per_pixel: usize,
+    height: usize,
+) -> Result<(), String> {
+    // Undo the filter of each row, in place. Filters predict a byte from
+    // the bytes to the left (a), above (b) and above left (c).
+    for y in 0..height {
+        let row_start = y * (1 + stride);
+        let filter = raw[row_start];
+        for x in 0..stride {
+            let p = row_start + 1 + x;
+            let a = if x >= bytes_per_pixel {
+                raw[p - bytes_per_pixel]
+            } else {
+                0
+            };
+            let b = if y > 0 { raw[p - (1 + stride)] } else { 0 };
+            let c = if y > 0 && x >= bytes_per_pixel {
+                raw[p - (1 + stride) - bytes_per_pixel]
+            } else {
+                0
+            };
+            let prediction = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => {
+                    // Paeth: whichever of a, b or c is closest to a + b - c
+                    let estimate = a as i16 + b as i16 - c as i16;
+                    let (pa, pb, pc) = (
+                        (estimate - a as i16).abs(),
+                        (estimate - b as i16).abs(),
+                        (estimate - c as i16).abs(),
+                    );
+                    if pa <= pb && pa <= pc {
+                        a
+                    } else if pb <= pc {
+                        b
+                    } else {
+                        c
+                    }
+                }
+                _ => return Err(format!("Unknown PNG filter {filter}")),
+            };
+            raw[p] = raw[p].wrapping_add(prediction);
+        }
+    }
+    Ok(())
+}
+
+// Reads deflate data bit by bit, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize, // In bits
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for n in 0..count {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or("Truncated deflate data".to_string())?;
+            value |= ((byte >> (self.position % 8)) as u32 & 1) << n;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+// Canonical Huffman code, as symbols ordered by code length
+struct Huffman {
+    counts: [u16; 16], // Number of codes of each length
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Symbols sorted by code length, then by value
+        let mut offsets = [0usize; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length] as usize;
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize]] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        // Codes of each length are consecutive, starting where the codes of
+        // the previous length end, doubled
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid deflate code".to_string())
+    }
+}
+
+fn inflate(data: &[u8], expected_len: usize) -> Result<Vec<u8>, String> {
+    // Decompress deflate blocks: stored, or Huffman coded with fixed or
+    // dynamic codes. More than expected_len bytes is an error, so that a
+    // corrupt file cannot use up memory.
+    let mut reader = BitReader { data, position: 0 };
+    let mut out = Vec::with_capacity(expected_len);
+    loop {
+        if out.len() > expected_len {
+            return Err("PNG data longer than the image".to_string());
+        }
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                // Stored: byte aligned length, its complement and the bytes
+                reader.position = reader.position.div_ceil(8) * 8;
+                let length = reader.bits(16)? as usize;
+                if reader.bits(16)? as usize != !length & 0xffff {
+                    return Err("Corrupt stored deflate block".to_string());
+                }
+                let start = reader.position / 8;
+                let bytes = data
+                    .get(start..start + length)
+                    .ok_or("Truncated deflate data".to_string())?;
+                out.extend_from_slice(bytes);
+                reader.position += length * 8;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &mut out, expected_len, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, expected_len, &literals, &distances)?;
+            }
+            _ => return Err("Invalid deflate block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    // Code lengths of both codes, themselves Huffman coded
+    let num_literals = reader.bits(5)? as usize + 257;
+    let num_distances = reader.bits(5)? as usize + 1;
+    let num_code_lengths = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &n in &CODE_LENGTH_ORDER[..num_code_lengths] {
+        code_lengths[n] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(num_literals + num_distances);
+    while lengths.len() < num_literals + num_distances {
+        let (length, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                // Repeat the previous length
+                let previous = *lengths
+                    .last()
+                    .ok_or("Invalid deflate lengths".to_string())?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    if lengths.len() > num_literals + num_distances {
+        return Err("Invalid deflate lengths".to_string());
+    }
+
+    let (literal_lengths, distance_lengths) = lengths.split_at(num_literals);
+    Ok((
+        Huffman::new(literal_lengths),
+        Huffman::new(distance_lengths),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    max_len: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    // Literal bytes, and copies of earlier output given as length and distance
+    loop {
+        if out.len() > max_len {
+            return Err("PNG data longer than the image".to_string());
+        }
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        } else if symbol == 256 {
+            return Ok(()); // End of block
+        }
+
+        let code = symbol - 257;
+        let (Some(&base), Some(&extra)) = (LENGTH_BASE.get(code), LENGTH_EXTRA.get(code)) else {
+            return Err("Invalid deflate length".to_string());
+        };
+        let length = base as usize + reader.bits(extra as u32)? as usize;
+
+        let code = distances.decode(reader)? as usize;
+        let (Some(&base), Some(&extra)) = (DISTANCE_BASE.get(code), DISTANCE_EXTRA.get(code))
+        else {
+            return Err("Invalid deflate distance".to_string());
+        };
+        let distance = base as usize + reader.bits(extra as u32)? as usize;
+        if distance > out.len() {
+            return Err("Deflate distance beyond start".to_string());
+        }
+
+        // Byte by byte, as the copy may overlap what it adds
+        let start = out.len() - distance;
+        for n in 0..length {
+            out.push(out[start + n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written by Python's zlib, not by encode_rgba(): RGB with every filter
+    // type and dynamic Huffman codes, and a palette with transparency and
+    // fixed Huffman codes
+    const GRADIENT_RGB: &[u8] = include_bytes!("../testdata/gradient_16_rgb.png");
+    const PALETTE_ALPHA: &[u8] = include_bytes!("../testdata/palette_4_alpha.png");
+
+    #[test]
+    fn encoded_images_decode_unchanged() {
+        // Larger than one stored block, with opaque, transparent and
+        // half-transparent pixels, colors premultiplied
+        let (width, height) = (200, 100);
+        let buffer: Vec<u32> = (0..width * height as u32)
+            .map(|n| match n % 3 {
+                0 => 0xff00_0000 | (n.wrapping_mul(2_654_435_761) >> 8),
+                1 => 0,
+                _ => 0x8000_407f | ((n % 128) << 16),
+            })
+            .collect();
+        let png = encode_rgba(&buffer, width as usize, height as usize);
+        let decoded = decode_rgba(&png).unwrap();
+        assert!(decoded == (buffer, width as usize, height as usize));
+    }
+
+    #[test]
+    fn external_images_decode() {
+        let (pixels, width, height) = decode_rgba(GRADIENT_RGB).unwrap();
+        assert_eq!((width, height), (16, 16));
+        for (n, &pixel) in pixels.iter().enumerate() {
+            let (x, y) = (n as u32 % 16, n as u32 / 16);
+            let expected = 0xff00_0000 | ((x * 16) << 16) | ((y * 16) << 8) | ((x + y) * 8);
+            assert_eq!(pixel, expected, "pixel ({x}, {y})");
+        }
+
+        // Red, transparent green and half-transparent blue, premultiplied
+        let (pixels, width, height) = decode_rgba(PALETTE_ALPHA).unwrap();
+        assert_eq!((width, height), (4, 4));
+        let colors = [0xff_ff_00_00, 0x00_00_00_00, 0x80_00_00_80];
+        for (n, &pixel) in pixels.iter().enumerate() {
+            assert_eq!(pixel, colors[(n % 4 + n / 4) % 3], "pixel {n}");
+        }
+    }
+
+    #[test]
+    fn truncated_and_corrupt_images_are_errors() {
+        for png in [
+            GRADIENT_RGB,
+            PALETTE_ALPHA,
+            &encode_rgba(&[0xff12_3456; 6], 3, 2),
+        ] {
+            // Cut anywhere before the end of the image data
+            let image_end = png.len() - 12; // Before the IEND chunk
+            for len in 0..image_end {
+                assert!(decode_rgba(&png[..len]).is_err(), "cut to {len} bytes");
+            }
+
+            // Any changed byte after the signature, caught by a CRC if not
+            // otherwise
+            for n in SIGNATURE.len()..png.len() {
+                let mut corrupt = png.to_vec();
+                corrupt[n] ^= 0x10;
+                assert!(decode_rgba(&corrupt).is_err(), "byte {n} changed");
+            }
+        }
+
+        // Changed image data with a matching CRC is caught by Adler-32
+        let mut png = encode_rgba(&[0xff12_3456; 6], 3, 2);
+        let idat = png.windows(4).position(|bytes| bytes == b"IDAT").unwrap();
+        let length = u32::from_be_bytes(png[idat - 4..idat].try_into().unwrap()) as usize;
+        png[idat + 4 + 8] ^= 0x01; // A pixel byte, after zlib and block headers and a filter byte
+        let crc = crc32(&png[idat..idat + 4 + length]);
+        png[idat + 4 + length..idat + 8 + length].copy_from_slice(&crc.to_be_bytes());
+        assert_eq!(
+            decode_rgba(&png).unwrap_err(),
+            "Corrupt PNG data, its Adler-32 differs"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+// recorder.rs - Record rendered frames to a video file by piping them to ffmpeg
+
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub struct Recorder {
+    filename: String,
+    size: (usize, usize),
+    child: Child,
+    stdin: ChildStdin,
+    start_time: Instant,
+    frame_buffer: Vec<u8>, // Frame converted to RGB bytes
+}
+
+impl Recorder {
+    pub fn start(size: (usize, usize)) -> io::Result<Self> {
+        // Name the file after the current time, to not overwrite earlier recordings
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let filename = format!("juggler-{secs}.mp4");
+
+        // Frames are taken as they come, so timestamp them with the wall clock
+        let (width, height) = size;
+        let mut child = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-video_size", &format!("{width}x{height}")])
+            .args(["-use_wallclock_as_timestamps", "1", "-i", "-"])
+            .args(["-pix_fmt", "yuv420p", &filename])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+
+        Ok(Self {
+            filename,
+            size,
+            child,
+            stdin,
+            start_time: Instant::now(),
+            frame_buffer: Vec::with_capacity(width * height * 3),
+        })
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn add_frame(&mut self, buffer: &[u32]) -> io::Result<()> {
+        // Frames must keep the size the recording was started with
+        let (width, height) = self.size;
+        if buffer.len() != width * height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame has {} pixels, the recording is {width}x{height}",
+                    buffer.len()
+                ),
+            ));
+        }
+
+        // Convert pixels to RGB bytes
+        self.frame_buffer.clear();
+        for &pixel in buffer {
+            self.frame_buffer.extend_from_slice(&[
+                (pixel >> 16 & 0xff) as u8, // R
+                (pixel >> 8 & 0xff) as u8,  // G
+                (pixel & 0xff) as u8,       // B
+            ]);
+        }
+
+        self.stdin.write_all(&self.frame_buffer)
+    }
+
+    pub fn finish(mut self) -> io::Result<(String, Duration)> {
+        let duration = self.start_time.elapsed();
+
+        // Closing the pipe lets ffmpeg finalize the file
+        drop(self.stdin);
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg exited with {status}")));
+        }
+
+        Ok((self.filename, duration))
+    }
+}
@@ -2,26 +2,68 @@
 
 use core::option::Option;
 use num_cpus;
-use std::sync::{Arc, Mutex};
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::f64::consts::{PI, TAU};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 use vecmath::{
     vec3_add, vec3_cross, vec3_dot, vec3_len, vec3_normalized, vec3_scale, vec3_sub, Vector3,
 };
 
-use crate::scene_juggler::populate_scene;
+use crate::scene_juggler;
 
-const DEFAULT_COLOR: (f64, f64, f64) = (0.5, 0.5, 0.5); // Window color at start
+const DEFAULT_COLOR: (f64, f64, f64) = (0.5, 0.5, 0.5); // Clear color unless set
 
-const RENDER_SPAN: usize = 64; // Number of pixels to render in one go
+const RENDER_SPAN: usize = 64; // Default number of pixels to render in one go, without tiles
 const RENDER_EPSILON: f64 = 0.0001; // Small distance away from a surface
+const MAX_SAMPLES_PER_AXIS: usize = 8; // Upper limit for supersampling
+const RECURSION_DEPTH: usize = 3; // Default number of reflection and see-through bounces
+const NO_HIT: u32 = u32::MAX; // Hit ID of a ray that did not hit anything
+pub const FIELD_OF_VIEW: f64 = 0.927_295_218_001_612_2; // Radians, 2 * atan(0.5)
+const MIN_RAY_WEIGHT: f64 = 1.0 / 255.0; // Below one 8-bit color step
+const DENOISE_RADIUS: usize = 2; // Denoise filter reach in pixels
+const BLOOM_SIGMA: f64 = 0.01; // Bloom blur radius relative to image height
+const EXPOSURE_KEY: f64 = 0.5; // Auto exposure target, mid gray as colors are not gamma encoded
+const EXPOSURE_RANGE: (f64, f64) = (0.25, 4.0); // Auto exposure limits, two stops either way
+const EXPOSURE_BINS: usize = 64; // Luminance histogram bins, over EXPOSURE_LOG2_RANGE
+const EXPOSURE_LOG2_RANGE: (f64, f64) = (-10.0, 6.0); // Luminance of the histogram, log2
+const EXPOSURE_TRIM: f64 = 0.05; // Share of darkest and brightest pixels ignored
+const PAGE_SIZE: usize = 4096; // Bytes, memory is touched at this spacing to fault it in
+const AO_POINTS: usize = 32; // Points sampled on each sphere for ambient occlusion
+const AO_RAYS: usize = 8; // Rays cast from each point
+const AO_DISTANCE: f64 = 2.0; // Occluders further away than this do not count
+const AO_REUSE_DISTANCE: f64 = 0.05; // Cached AO is kept while a sphere moves less
+const TIME_SAMPLES: usize = 4; // Most scenes populated per frame for time jitter
+const WORKER_NAME: &str = "render-worker"; // Render threads are named this, dash, index
+const CHECKER_SCALING_DISTANCE: f64 = 16.0; // Distance-scaled checkers grow beyond this
 
-#[derive(Clone)]
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325; // 64-bit FNV-1a, for frame_hash()
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// Ordered dithering thresholds, in sixteenths of a quantization step
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,     // Viewport one unit wide at unit distance along forward
+    Equirectangular, // Full 360° panorama around pos, for a 2:1 image
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Camera {
     pub pos: Vector3<f64>,
     pub right: Vector3<f64>,
     pub up: Vector3<f64>,
     pub forward: Vector3<f64>,
+    pub projection: Projection,
 }
 
 impl Camera {
@@ -30,54 +72,616 @@ impl Camera {
         self.right = vec3_normalized(vec3_cross(self.up, self.forward));
         self.up = vec3_normalized(vec3_cross(self.forward, self.right));
     }
+
+    #[allow(dead_code)]
+    pub fn orbit(&mut self, center: Vector3<f64>, d_azimuth: f64, d_elevation: f64) {
+        // Rotate position around center, azimuth around the Y axis and
+        // elevation towards it, keeping the distance to center
+        let offset = vec3_sub(self.pos, center);
+        let distance = vec3_len(offset);
+        if distance == 0.0 {
+            return;
+        }
+
+        // Stay clear of the poles, where the Y axis cannot be used as up
+        let max_elevation = PI / 2.0 - 0.01;
+        let azimuth = offset[0].atan2(offset[2]) + d_azimuth;
+        let elevation =
+            ((offset[1] / distance).asin() + d_elevation).clamp(-max_elevation, max_elevation);
+
+        self.pos = vec3_add(
+            center,
+            [
+                distance * elevation.cos() * azimuth.sin(),
+                distance * elevation.sin(),
+                distance * elevation.cos() * azimuth.cos(),
+            ],
+        );
+        self.up = [0.0, 1.0, 0.0];
+        self.look_at(center);
+    }
+
+    #[allow(dead_code)]
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        // Move sideways and vertically in the view plane, the view direction
+        // (and so the basis) stays the same
+        let movement = vec3_add(vec3_scale(self.right, dx), vec3_scale(self.up, dy));
+        self.pos = vec3_add(self.pos, movement);
+    }
+
+    #[allow(dead_code)]
+    pub fn dolly(&mut self, dz: f64) {
+        // Move forward (or backward if negative) along the view direction
+        self.pos = vec3_add(self.pos, vec3_scale(self.forward, dz));
+    }
+
+    pub fn frame(&mut self, bounds: (Vector3<f64>, Vector3<f64>), fov: f64) {
+        // Move back along the view direction until a sphere around the
+        // bounds fits the field of view, then look at its center
+        let (min, max) = bounds;
+        let center = vec3_scale(vec3_add(min, max), 0.5);
+        let radius = vec3_len(vec3_sub(max, min)) / 2.0;
+        let distance = radius / (fov / 2.0).sin();
+
+        self.pos = vec3_sub(center, vec3_scale(vec3_normalized(self.forward), distance));
+        self.up = [0.0, 1.0, 0.0];
+        self.look_at(center);
+    }
+
+    pub fn lerp(&self, other: &Camera, t: f64) -> Camera {
+        // Blend position and view direction, t = 0.0: self, 1.0: other
+        let blend =
+            |a: Vector3<f64>, b: Vector3<f64>| vec3_add(vec3_scale(a, 1.0 - t), vec3_scale(b, t));
+        let mut camera = Camera {
+            pos: blend(self.pos, other.pos),
+            right: [1.0, 0.0, 0.0],
+            up: blend(self.up, other.up),
+            forward: [0.0, 0.0, 1.0],
+            projection: self.projection,
+        };
+        camera.look_at(vec3_add(camera.pos, blend(self.forward, other.forward)));
+        camera
+    }
+
+    pub fn primary_ray(
+        &self,
+        x: f64, // Pixel coordinates, pixel centers at whole numbers
+        y: f64,
+        width: usize,
+        height: usize,
+    ) -> (Vector3<f64>, Vector3<f64>) {
+        // Origin and direction of the ray through a pixel. The direction ends
+        // on the viewport, one unit in front of the camera, so its length is
+        // the distance to the viewport.
+        if self.projection == Projection::Equirectangular {
+            return (self.pos, equirectangular_dir(x, y, width, height));
+        }
+
+        // Corner pixel centers on the viewport edges, a single pixel at a corner
+        let vx = (x / width.saturating_sub(1).max(1) as f64) - 0.5;
+        let vy = 0.5 - (y / height.saturating_sub(1).max(1) as f64);
+        let dir = vec3_add(
+            vec3_add(self.forward, vec3_scale(self.right, vx)),
+            vec3_scale(self.up, vy),
+        );
+        (self.pos, dir)
+    }
+
+    pub fn project(&self, pos: Vector3<f64>) -> Option<(f64, f64)> {
+        // Project a world position to viewport coordinates (-0.5..0.5 when
+        // visible), inverse of primary_ray()
+        let offset = vec3_sub(pos, self.pos);
+        if self.projection == Projection::Equirectangular {
+            // Longitude and latitude, everything is visible
+            let distance = vec3_len(offset);
+            if distance == 0.0 {
+                return None;
+            }
+            let longitude = offset[0].atan2(offset[2]);
+            let latitude = (offset[1] / distance).asin();
+            return Some((longitude / TAU, latitude / PI));
+        }
+
+        let depth = vec3_dot(offset, self.forward);
+        if depth <= 0.0 {
+            // Behind the camera
+            return None;
+        }
+
+        let vx = vec3_dot(offset, self.right) / depth;
+        let vy = vec3_dot(offset, self.up) / depth;
+        Some((vx, vy))
+    }
+
+    pub fn projected_radius(&self, pos: Vector3<f64>, r: f64) -> Option<f64> {
+        // Approximate radius of a sphere in viewport units, the viewport is
+        // one unit wide at unit distance along the forward vector
+        let offset = vec3_sub(pos, self.pos);
+        if self.projection == Projection::Equirectangular {
+            // Viewport height spans half a turn of latitude
+            return Some(r / vec3_len(offset) / PI);
+        }
+        if vec3_dot(offset, self.forward) <= 0.0 {
+            // Behind the camera
+            return None;
+        }
+
+        Some(r / vec3_len(offset))
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
 pub enum Texture {
     Color(f64, f64, f64),
     CheckerXZ {
         color1: (f64, f64, f64),
         color2: (f64, f64, f64),
         scale: f64,
+        #[cfg_attr(feature = "serde", serde(default))]
+        distance_scaling: bool, // Squares double in size with each doubling of distance
+    },
+    Grid {
+        line_color: (f64, f64, f64),
+        bg_color: (f64, f64, f64),
+        spacing: f64,    // Distance between lines along X and along Z
+        line_width: f64, // Width of a line, less than spacing
     },
     GradientY {
         color1: (f64, f64, f64),
         color2: (f64, f64, f64),
     },
+    Gradient {
+        color1: (f64, f64, f64), // Towards axis
+        color2: (f64, f64, f64), // Away from axis
+        axis: Vector3<f64>,      // Direction from sphere center, need not be normalized
+    },
 }
 
-#[derive(Clone)]
-pub struct Sphere {
-    pub pos: Vector3<f64>,
-    pub r: f64,
+// Surface properties of a sphere, can be shared by many spheres
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct Material {
     pub texture: Texture,
     pub specular: f64,
-    pub reflective: f64,
+    pub reflective: f64, // Share of reflected color, for dielectrics when seen head-on
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub metallic: f64, // 0.0: Dielectric, reflects white, 1.0: Metal, tints reflections
     pub skip_lighting: bool,
+    pub one_sided: bool,
+    pub opacity: f64, // 1.0: Opaque, less blends in what is behind the sphere
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shadow_catcher: bool, // Invisible except for shadows falling on it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub emission: (f64, f64, f64), // Light given off, added regardless of lighting
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ripple: Option<Ripple>, // Waves on the surface, seen in reflections only
+}
+
+// Waves that tilt the normal used for reflections, like water. Sums a few
+// sine waves in X and Z of different lengths and directions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct Ripple {
+    pub amplitude: f64,  // Steepest slope of a wave, 0.0: flat
+    pub wavelength: f64, // Length of the longest wave
+    pub phase: f64,      // In radians, advanced over time to animate
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Sphere {
+    pub pos: Vector3<f64>,
+    pub r: f64, // Negative: inside out, the normal points inward, e.g. for a room
+    pub material: Material,
+}
+
+impl Sphere {
+    pub fn bounding_box(&self) -> (Vector3<f64>, Vector3<f64>) {
+        // Minimum and maximum corners
+        let r = [self.r.abs(); 3];
+        (vec3_sub(self.pos, r), vec3_add(self.pos, r))
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Light {
-    Ambient { intensity: f64 },
-    Point { intensity: f64, pos: Vector3<f64> },
-    Directional { intensity: f64, dir: Vector3<f64> },
+    Ambient {
+        intensity: f64,
+    },
+    HemisphereAmbient {
+        sky_color: (f64, f64, f64),    // For normals pointing straight up
+        ground_color: (f64, f64, f64), // For normals pointing straight down
+    },
+    Point {
+        intensity: f64,
+        pos: Vector3<f64>,
+    },
+    Directional {
+        intensity: f64,
+        dir: Vector3<f64>,      // Towards the light
+        color: (f64, f64, f64), // Tint, multiplied by intensity
+    },
 }
 
-#[derive(Clone)]
+// Color of rays that do not hit anything, by ray direction
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Environment {
+    Color(f64, f64, f64),
+    GradientY {
+        color1: (f64, f64, f64), // Straight up
+        color2: (f64, f64, f64), // Straight down
+    },
+    Checker {
+        color1: (f64, f64, f64),
+        color2: (f64, f64, f64),
+        scale: f64, // Size of a square in radians
+    },
+}
+
+// Problem found by Scene::validate()
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneWarning {
+    NonFiniteSphere { sphere: usize }, // Position or radius is NaN or infinite
+    ZeroRadius { sphere: usize },
+    DegenerateCamera, // Position or a basis vector non-finite, or basis vector of zero length
+    NonFiniteLight { light: usize }, // Intensity, color, position or direction
+    ZeroLightDirection { light: usize },
+}
+
+impl fmt::Display for SceneWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneWarning::NonFiniteSphere { sphere } => {
+                write!(f, "Sphere {sphere} has a non-finite position or radius")
+            }
+            SceneWarning::ZeroRadius { sphere } => {
+                write!(f, "Sphere {sphere} has zero radius")
+            }
+            SceneWarning::DegenerateCamera => {
+                write!(
+                    f,
+                    "Camera has a non-finite or zero-length position or basis"
+                )
+            }
+            SceneWarning::NonFiniteLight { light } => {
+                write!(f, "Light {light} has a non-finite value")
+            }
+            SceneWarning::ZeroLightDirection { light } => {
+                write!(f, "Light {light} has a zero-length direction")
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Scene {
     pub camera: Camera,
     pub spheres: Vec<Sphere>,
     pub lights: Vec<Light>,
-    pub sky_color: (f64, f64, f64),
+    pub environment: Environment,
+    ambient_occlusion: Vec<f64>, // Baked per sphere, 1.0: unoccluded, empty: not baked
+}
+
+impl Scene {
+    #[allow(dead_code)]
+    pub fn bounds(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        // Bounding box of all spheres, None if there are none
+        self.bounds_of(|_| true)
+    }
+
+    pub fn bounds_of<F>(&self, filter: F) -> Option<(Vector3<f64>, Vector3<f64>)>
+    where
+        F: Fn(&Sphere) -> bool,
+    {
+        // Bounding box of spheres accepted by filter
+        self.spheres
+            .iter()
+            .filter(|sphere| filter(sphere))
+            .map(Sphere::bounding_box)
+            .reduce(|(min_a, max_a), (min_b, max_b)| {
+                (
+                    [
+                        min_a[0].min(min_b[0]),
+                        min_a[1].min(min_b[1]),
+                        min_a[2].min(min_b[2]),
+                    ],
+                    [
+                        max_a[0].max(max_b[0]),
+                        max_a[1].max(max_b[1]),
+                        max_a[2].max(max_b[2]),
+                    ],
+                )
+            })
+    }
+
+    #[allow(dead_code)]
+    pub fn set_daylight(
+        &mut self,
+        sun_dir: Vector3<f64>, // Towards the sun
+        sun_color: (f64, f64, f64),
+        turbidity: f64, // Haziness, 2: clear blue sky, 10: overcast
+    ) {
+        // Outdoor lighting: a sun, a sky lighting from above and a sky
+        // environment. Replaces ambient and directional lights, keeps point
+        // lights. The added lights are regular ones, to be adjusted after.
+        self.lights
+            .retain(|light| matches!(light, Light::Point { .. }));
+
+        // Clear sky is deep blue, hazy sky is pale
+        let haze = ((turbidity - 2.0) / 8.0).clamp(0.0, 1.0);
+        let mix = |clear: f64, hazy: f64| clear + (hazy - clear) * haze;
+        let sky_color = (mix(0.25, 0.75), mix(0.45, 0.78), mix(0.9, 0.82));
+        let horizon_color = (mix(0.7, 0.85), mix(0.8, 0.86), mix(1.0, 0.88));
+
+        // Haze scatters sunlight into the sky
+        let sun_intensity = 0.8 - 0.4 * haze;
+        let sky_intensity = 0.3 + 0.2 * haze;
+        self.lights.extend([
+            Light::Directional {
+                intensity: sun_intensity,
+                dir: sun_dir,
+                color: sun_color,
+            },
+            Light::HemisphereAmbient {
+                sky_color: (
+                    sky_color.0 * sky_intensity,
+                    sky_color.1 * sky_intensity,
+                    sky_color.2 * sky_intensity,
+                ),
+                ground_color: (
+                    sun_color.0 * sky_intensity * 0.5,
+                    sun_color.1 * sky_intensity * 0.5,
+                    sun_color.2 * sky_intensity * 0.5,
+                ),
+            },
+        ]);
+
+        self.environment = Environment::GradientY {
+            color1: sky_color,
+            color2: horizon_color,
+        };
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<SceneWarning>> {
+        // Check for mistakes that would render garbage instead of failing,
+        // such as the zero radius of a sphere used as a prototype
+        let mut warnings = Vec::new();
+        let finite = |v: Vector3<f64>| v.iter().all(|c| c.is_finite());
+        let finite_color =
+            |c: (f64, f64, f64)| c.0.is_finite() && c.1.is_finite() && c.2.is_finite();
+
+        let camera = &self.camera;
+        let basis = [camera.right, camera.up, camera.forward];
+        if !finite(camera.pos) || basis.iter().any(|&v| !finite(v) || vec3_len(v) == 0.0) {
+            warnings.push(SceneWarning::DegenerateCamera);
+        }
+
+        for (n, sphere) in self.spheres.iter().enumerate() {
+            if !finite(sphere.pos) || !sphere.r.is_finite() {
+                warnings.push(SceneWarning::NonFiniteSphere { sphere: n });
+            } else if sphere.r == 0.0 {
+                warnings.push(SceneWarning::ZeroRadius { sphere: n });
+            }
+        }
+
+        for (n, light) in self.lights.iter().enumerate() {
+            let (finite_values, dir) = match light {
+                Light::Ambient { intensity } => (intensity.is_finite(), None),
+                Light::HemisphereAmbient {
+                    sky_color,
+                    ground_color,
+                } => (
+                    finite_color(*sky_color) && finite_color(*ground_color),
+                    None,
+                ),
+                Light::Point { intensity, pos } => (intensity.is_finite() && finite(*pos), None),
+                Light::Directional {
+                    intensity,
+                    dir,
+                    color,
+                } => (
+                    intensity.is_finite() && finite(*dir) && finite_color(*color),
+                    Some(*dir),
+                ),
+            };
+            if !finite_values {
+                warnings.push(SceneWarning::NonFiniteLight { light: n });
+            } else if dir.is_some_and(|dir| vec3_len(dir) == 0.0) {
+                warnings.push(SceneWarning::ZeroLightDirection { light: n });
+            }
+        }
+
+        if warnings.is_empty() {
+            Ok(())
+        } else {
+            Err(warnings)
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        // Plain text dump of the scene, for debugging
+        let camera = &self.camera;
+        let mut text = format!(
+            "Camera: pos {:?}, right {:?}, up {:?}, forward {:?}\n",
+            camera.pos, camera.right, camera.up, camera.forward
+        );
+
+        text += &format!("Environment: {:?}\n", self.environment);
+
+        text += &format!("Lights: {}\n", self.lights.len());
+        for (n, light) in self.lights.iter().enumerate() {
+            text += &format!("  {n}: {light:?}\n");
+        }
+
+        text += &format!("Spheres: {}\n", self.spheres.len());
+        for (n, sphere) in self.spheres.iter().enumerate() {
+            text += &format!(
+                "  {}: pos {:?}, r {}, texture {:?}, specular {}, reflective {}, \
+                 metallic {}, skip_lighting {}, one_sided {}, opacity {}, shadow_catcher {}, \
+                 emission {:?}, ripple {:?}\n",
+                n,
+                sphere.pos,
+                sphere.r,
+                sphere.material.texture,
+                sphere.material.specular,
+                sphere.material.reflective,
+                sphere.material.metallic,
+                sphere.material.skip_lighting,
+                sphere.material.one_sided,
+                sphere.material.opacity,
+                sphere.material.shadow_catcher,
+                sphere.material.emission,
+                sphere.material.ripple
+            );
+        }
+
+        text
+    }
+}
+
+#[allow(dead_code)]
+pub fn populate_interpolated(scene: &mut Scene, scene_a: &Scene, scene_b: &Scene, t: f64) {
+    // Morph between two keyframe scenes, t = 0.0: scene_a, 1.0: scene_b.
+    // Spheres are matched by index, blending position, radius and plain
+    // colors, other material settings switch halfway. Spheres only in one
+    // scene fade in or out by opacity, casting full shadows until gone at
+    // the other end. The camera is blended, lights and environment switch
+    // halfway.
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: f64, b: f64| a + (b - a) * t;
+    let nearest = if t < 0.5 { scene_a } else { scene_b };
+
+    scene.spheres.clear();
+    for n in 0..scene_a.spheres.len().max(scene_b.spheres.len()) {
+        let sphere = match (scene_a.spheres.get(n), scene_b.spheres.get(n)) {
+            (Some(a), Some(b)) => {
+                let mut sphere = if t < 0.5 { a.clone() } else { b.clone() };
+                sphere.pos = [
+                    mix(a.pos[0], b.pos[0]),
+                    mix(a.pos[1], b.pos[1]),
+                    mix(a.pos[2], b.pos[2]),
+                ];
+                sphere.r = mix(a.r, b.r);
+                if let (Texture::Color(ar, ag, ab), Texture::Color(br, bg, bb)) =
+                    (&a.material.texture, &b.material.texture)
+                {
+                    sphere.material.texture =
+                        Texture::Color(mix(*ar, *br), mix(*ag, *bg), mix(*ab, *bb));
+                }
+                sphere
+            }
+            (Some(_), None) if t == 1.0 => continue,
+            (None, Some(_)) if t == 0.0 => continue,
+            (Some(a), None) => {
+                let mut sphere = a.clone();
+                sphere.material.opacity *= 1.0 - t;
+                sphere
+            }
+            (None, Some(b)) => {
+                let mut sphere = b.clone();
+                sphere.material.opacity *= t;
+                sphere
+            }
+            (None, None) => unreachable!(),
+        };
+        scene.spheres.push(sphere);
+    }
+
+    scene.camera = scene_a.camera.lerp(&scene_b.camera, t);
+    scene.lights.clone_from(&nearest.lights);
+    scene.environment = nearest.environment.clone();
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Shaded,       // Regular rendering
+    DiffuseOnly,  // Only diffuse lighting, no ambient or specular
+    SpecularOnly, // Only specular highlights
+    PathTrace,    // Diffuse light bounced between surfaces, no ambient or specular
+}
+
+// Combinations of quality settings, from fastest to best looking
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QualityPreset {
+    Draft,    // One sample per pixel, one bounce
+    Balanced, // Supersampled edges, default bounces
+    High,     // More samples on edges, more bounces, ambient occlusion
+    Ultra,    // Full supersampling, many bounces, ambient occlusion, denoised
+}
+
+impl QualityPreset {
+    pub fn next(self) -> Self {
+        // Cycle through presets, back to Draft after Ultra
+        match self {
+            QualityPreset::Draft => QualityPreset::Balanced,
+            QualityPreset::Balanced => QualityPreset::High,
+            QualityPreset::High => QualityPreset::Ultra,
+            QualityPreset::Ultra => QualityPreset::Draft,
+        }
+    }
+}
+
+// Silhouette edges drawn over the shaded image
+#[derive(Clone, Copy)]
+pub struct Outline {
+    pub width: usize,           // Pixels on each side of an edge
+    pub color: (f64, f64, f64), // Linear color
+}
+
+// Highlight of what is at a distance from the camera, for finding the focal plane
+#[derive(Clone, Copy)]
+pub struct FocusPeaking {
+    pub distance: f64,          // Distance of the highlighted surfaces from the camera
+    pub tolerance: f64,         // Surfaces this much nearer or further are highlighted too
+    pub color: (f64, f64, f64), // Linear color
+}
+
+// Glow around bright pixels
+#[derive(Clone, Copy)]
+pub struct Bloom {
+    pub threshold: f64, // Luminance above which pixels glow
+    pub intensity: f64, // Amount of glow added to the image
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct RenderStats {
+    pub rays: u64,                // Primary, reflection and shadow rays
+    pub sphere_tests: u64,        // Ray-sphere intersection tests
+    pub skipped_reflections: u64, // Reflections predicted dark and not traced
 }
 
+#[derive(Clone)]
 pub struct SceneOptions {
     pub speed_0: f64, // Scene decides what these mean
     pub speed_1: f64,
+    // Seconds added to time * speed_0 and time * speed_1, so that a speed
+    // can change without the animation jumping
+    pub offset_0: f64,
+    pub offset_1: f64,
     pub option_0: bool,
     pub option_1: bool,
+    pub light_intensity: f64, // Scale of the first point light's intensity
+    pub hidden_groups: u32,   // Bit mask of object groups not to render
+}
+
+impl Default for SceneOptions {
+    fn default() -> Self {
+        // Everything at normal speed and intensity, nothing hidden
+        Self {
+            speed_0: 1.0,
+            speed_1: 1.0,
+            offset_0: 0.0,
+            offset_1: 0.0,
+            option_0: false,
+            option_1: false,
+            light_intensity: 1.0,
+            hidden_groups: 0,
+        }
+    }
 }
 
 // Public name for the shared Renderer type
@@ -86,24 +690,308 @@ pub type SharedRenderer = Arc<Renderer>;
 // Shared render buffer wrapped in Arc and Mutex
 pub type SharedBuffer = Arc<Mutex<Vec<u32>>>;
 
+// Shared linear (unquantized) color buffer wrapped in Arc and Mutex
+type SharedHdrBuffer = Arc<Mutex<Vec<(f64, f64, f64)>>>;
+
+// Function called with each completed frame, its width and height, and render duration
+type FrameCallback = Box<dyn Fn(&[u32], usize, usize, Duration) + Send + 'static>;
+
+// Function giving the color of rays that miss every sphere, from the
+// normalized ray direction and the scene time
+pub type BackgroundCallback = Arc<dyn Fn(Vector3<f64>, Duration) -> (f64, f64, f64) + Send + Sync>;
+
+// Function that fills in a scene for a given time and options
+pub type PopulateScene = fn(&mut Scene, Duration, &SceneOptions);
+
+thread_local! {
+    // Statistics of the current render thread
+    static THREAD_STATS: Cell<RenderStats> = const {
+        Cell::new(RenderStats {
+            rays: 0,
+            sphere_tests: 0,
+            skipped_reflections: 0,
+        })
+    };
+
+    // Random number generator state of the current render thread, 0: not seeded
+    static RNG_STATE: Cell<u64> = const { Cell::new(0) };
+
+    // Span and pixel the current render thread is working on, for panic
+    // messages, None: not rendering pixels, e.g., running callbacks
+    static WORK_POSITION: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+// Miss color from a callback instead of the scene's environment
+#[derive(Clone)]
+struct Background {
+    callback: BackgroundCallback,
+    primary_only: bool, // Reflections and bounces still see the environment
+}
+
+// Settings that affect shading, copied to each render thread
+#[derive(Clone)]
+struct TraceSettings {
+    render_mode: RenderMode,
+    min_ray_weight: f64, // Secondary rays contributing less are not traced
+    dark_reflection_cutoff: f64, // Reflections predicted to add less luminance are not traced
+    transparent_background: bool, // Rays missing all spheres have alpha 0
+    transparent_reflections: bool, // Background in reflections is transparent, not sky
+    environment_in_reflections: bool, // Reflected full-bright spheres show the environment
+    filter_textures: bool, // Average checker textures over the pixel footprint
+    recursion_depth: usize, // Bounces of reflection and see-through rays
+    emissive_lights: bool, // Emissive spheres light other surfaces
+    shadow_tint: (f64, f64, f64), // Share of a light kept in its shadows, per channel
+    sky_rotation: f64,   // Yaw of the environment in radians
+    background: Option<Background>, // Replaces the environment, None: environment
+    pixel_size: f64,     // Viewport units per pixel, set for each render
+    time: Duration,      // Scene time, set for each render
+}
+
+// Division of the image into spans, the units of work for render threads
+#[derive(Clone, Copy)]
+struct SpanLayout {
+    width: usize,
+    height: usize,
+    span_size: usize,         // Pixels per span in row-major order, without tiles
+    tile_size: Option<usize>, // Square tiles, None: span_size pixels in row-major order
+}
+
+impl SpanLayout {
+    fn num_spans(&self) -> usize {
+        match self.tile_size {
+            None => (self.width * self.height).div_ceil(self.span_size),
+            Some(tile_size) => self.width.div_ceil(tile_size) * self.height.div_ceil(tile_size),
+        }
+    }
+
+    fn max_span_pixels(&self) -> usize {
+        // Pixels in the largest span, spans at the right and bottom edges may be smaller
+        match self.tile_size {
+            None => self.span_size.min(self.width * self.height),
+            Some(tile_size) => tile_size.min(self.width) * tile_size.min(self.height),
+        }
+    }
+
+    fn span_pixels(&self, span: usize, ranges: &mut Vec<Range<usize>>) {
+        // Pixels of a span as ranges of consecutive pixels, one per tile row
+        ranges.clear();
+        match self.tile_size {
+            None => {
+                // The last span is shorter, if the pixel count is not divisible
+                let start = span * self.span_size;
+                ranges.push(start..(start + self.span_size).min(self.width * self.height));
+            }
+            Some(tile_size) => {
+                let tiles_per_row = self.width.div_ceil(tile_size);
+                let left_x = (span % tiles_per_row) * tile_size;
+                let top_y = (span / tiles_per_row) * tile_size;
+                let right_x = (left_x + tile_size).min(self.width);
+                for y in top_y..(top_y + tile_size).min(self.height) {
+                    ranges.push((y * self.width + left_x)..(y * self.width + right_x));
+                }
+            }
+        }
+    }
+}
+
+// Buffers of a render thread, kept from frame to frame in a pool, as render
+// threads are started for each frame
+#[derive(Default)]
+struct SpanBuffers {
+    span_ranges: Vec<Range<usize>>, // Pixels of the span, one range per tile row
+    span_buffer: Vec<u32>,          // Rendered colors of the span
+    span_hdr_buffer: Vec<(f64, f64, f64)>, // Linear colors of the span
+    span_hit_ids: Vec<u32>,         // Sphere hit by each pixel of the span
+    edge_pixels: Vec<(usize, (f64, f64, f64), f64)>, // Pixels supersampled in the second pass
+}
+
+// Scene kept from frame to frame, so that populating it reuses allocations
+struct SceneCache {
+    scene: Arc<Scene>,             // Shared with render threads while rendering
+    time_samples: Vec<Arc<Scene>>, // Same scene later in the frame, for time jitter
+    populate: PopulateScene,       // Scene to render
+}
+
+impl SceneCache {
+    fn update(
+        &mut self,
+        duration_since_start: Duration,
+        scene_options: &SceneOptions,
+        camera_override: Option<&Camera>,
+    ) {
+        // Render threads of the previous frame have ended, so the scene is not
+        // shared and is updated in place. Otherwise, it is cloned first.
+        let scene = Arc::make_mut(&mut self.scene);
+        (self.populate)(scene, duration_since_start, scene_options);
+
+        // Viewer's camera replaces the one defined by the scene
+        if let Some(camera) = camera_override {
+            scene.camera = camera.clone();
+        }
+    }
+
+    fn update_time_samples(
+        &mut self,
+        duration_since_start: Duration,
+        interval: Duration,
+        num_scenes: usize, // Including the scene at the frame's own time
+        scene_options: &SceneOptions,
+        camera_override: Option<&Camera>,
+    ) {
+        // Populate the scene at evenly spaced later times within the
+        // interval, reusing the previous frame's scenes
+        let num_time_samples = num_scenes.saturating_sub(1);
+        self.time_samples.truncate(num_time_samples);
+        while self.time_samples.len() < num_time_samples {
+            self.time_samples.push(Arc::clone(&self.scene));
+        }
+        for (n, time_sample) in self.time_samples.iter_mut().enumerate() {
+            let offset = interval.mul_f64((n + 1) as f64 / num_scenes as f64);
+            let scene = Arc::make_mut(time_sample);
+            (self.populate)(scene, duration_since_start + offset, scene_options);
+            if let Some(camera) = camera_override {
+                scene.camera = camera.clone();
+            }
+        }
+    }
+
+    fn bake_ambient_occlusion(&mut self, cache: &mut Vec<AoCacheEntry>) {
+        // Spheres are identified by their index, as scenes add them in the
+        // same order every frame. A sphere that moved less than
+        // AO_REUSE_DISTANCE keeps its cached AO, even though other spheres
+        // near it may have moved: slightly stale shading for a fraction of
+        // the work.
+        let scene = Arc::make_mut(&mut self.scene);
+        cache.truncate(scene.spheres.len());
+        scene.ambient_occlusion.clear();
+        for (sphere_id, sphere) in scene.spheres.iter().enumerate() {
+            let cached = cache.get(sphere_id).filter(|entry| {
+                vec3_len(vec3_sub(entry.pos, sphere.pos)) <= AO_REUSE_DISTANCE
+                    && (entry.r - sphere.r).abs() <= AO_REUSE_DISTANCE
+            });
+            let ao = match cached {
+                Some(entry) => entry.ao,
+                None => {
+                    let entry = AoCacheEntry {
+                        pos: sphere.pos,
+                        r: sphere.r,
+                        ao: sphere_ambient_occlusion(scene, sphere),
+                    };
+                    if sphere_id < cache.len() {
+                        cache[sphere_id] = entry;
+                    } else {
+                        cache.push(entry);
+                    }
+                    entry.ao
+                }
+            };
+            scene.ambient_occlusion.push(ao);
+        }
+    }
+
+    fn cull_small_spheres(&mut self, min_screen_radius: f64, height: usize) {
+        // Drop spheres in front of the camera that are smaller than
+        // min_screen_radius pixels. Spheres behind the camera may still be
+        // seen in reflections, so they are kept.
+        let pixels_per_unit = height.saturating_sub(1) as f64;
+        for scene in std::iter::once(&mut self.scene).chain(self.time_samples.iter_mut()) {
+            let scene = Arc::make_mut(scene);
+            let camera = scene.camera.clone();
+            scene.spheres.retain(|sphere| {
+                camera
+                    .projected_radius(sphere.pos, sphere.r.abs())
+                    .is_none_or(|r| r * pixels_per_unit >= min_screen_radius)
+            });
+        }
+    }
+}
+
+// Ambient occlusion of a sphere, and where it was when baked
+#[derive(Clone, Copy)]
+struct AoCacheEntry {
+    pos: Vector3<f64>,
+    r: f64,
+    ao: f64,
+}
+
 // Shared private data wrapped in Arc and Mutex
 struct SharedData {
     width: usize,
     height: usize,
-    scene: Scene,
-    buffer_0_active: bool, // true: Rendering to buffer_0, false: buffer_1
-    next_pixel: usize,
+    scene_cache: SceneCache,
+    camera_override: Option<Camera>, // Used instead of the scene's camera
+    max_spheres: Option<usize>,      // Warn about scenes with more spheres
+    max_lights: Option<usize>,       // Warn about scenes with more lights
+    over_limits: bool,               // Warned already, until back within limits
+    scene_invalid: bool,             // Warned about validation, until valid again
+    buffer_0_active: bool,           // true: Rendering to buffer_0, false: buffer_1
+    span_layout: SpanLayout,         // Layout of the render in progress
+    span_size: usize,                // Layout of following renders
+    tile_size: Option<usize>,
+    next_span: usize,
+    next_edge_span: usize, // Second pass of adaptive supersampling
     num_pixels: usize,
     start_time: Instant,
+    deadline: Option<Instant>,    // Stop rendering new spans after this
+    skipped_spans: Option<usize>, // First span left unrendered when the deadline passed
     duration: Duration,
     threads: Vec<JoinHandle<()>>,
+    running_threads: usize,       // Render threads not done with the frame yet
+    first_pass_threads: usize,    // Render threads not done with the first pass yet
+    cancelled: bool,              // Render abandoned, not to be completed
+    num_threads: usize,           // Render threads per frame
+    samples_per_axis: usize,      // Supersampling, 1: one sample per pixel
+    adaptive_supersampling: bool, // Supersample geometry edges only
+    coverage_antialiasing: bool,  // Blend silhouette pixels by sphere coverage
+    firefly_clamp: Option<f64>,   // Maximum luminance of a single sample
+    min_screen_radius: Option<f64>, // Skip spheres smaller than this, in pixels
+    dithering: bool,              // Dither colors before quantization
+    gamma: f64,                   // Display gamma, 1.0: colors are written linear
+    clear_color: (f64, f64, f64), // Color of new buffers, and window borders
+    outline: Option<Outline>,     // Draw silhouette edges after rendering
+    focus_peaking: Option<FocusPeaking>, // Highlight surfaces at a distance after rendering
+    bloom: Option<Bloom>,         // Make bright pixels glow after rendering
+    denoise: Option<f64>,         // Color difference that is blurred over
+    artificial_delay: Option<Duration>, // Sleep before each span, simulating a slow computer
+    accumulate: bool,             // Average frames while the scene stays the same
+    accumulated_frames: usize,    // Frames summed in accumulation_buffer
+    accumulated_scene: Option<Arc<Scene>>, // Scene being accumulated, with its camera
+    accumulated_exposure: f64,    // Exposure of the frames being accumulated
+    ambient_occlusion: bool,      // Darken ambient light by baked occlusion
+    ao_cache: Vec<AoCacheEntry>,  // Baked AO by sphere index, kept between frames
+    time_jitter: Option<Duration>, // Supersamples spread over this time after the frame's
+    interleaved: bool,            // Render half of the pixels per frame, in a checkerboard
+    interleave_phase: usize,      // Which half of the checkerboard is rendered, 0 or 1
+    exposure: f64,                // Scale of linear colors, when not automatic
+    auto_exposure: Option<f64>,   // Adaptation speed per second, None: manual exposure
+    adapted_exposure: f64,        // Automatic exposure for the next frame
+    adapted_at: Option<Instant>,  // When automatic exposure was last adapted
+    frame_exposure: f64,          // Exposure of the frame being rendered
+    trace_settings: TraceSettings,
+    stats: RenderStats,
+    callback_panic: Option<String>, // Message of the latest panicking callback
+}
+
+impl SharedData {
+    fn restart_accumulation(&mut self) {
+        // The next frame is averaged with no earlier ones
+        self.accumulated_frames = 0;
+    }
 }
 
 pub struct Renderer {
     buffer_0: SharedBuffer,
     buffer_1: SharedBuffer,
+    hdr_buffer_0: SharedHdrBuffer,        // Linear colors of buffer_0
+    hdr_buffer_1: SharedHdrBuffer,        // Linear colors of buffer_1
+    hit_id_buffer: SharedBuffer,          // Sphere hit by each pixel's primary ray
+    accumulation_buffer: SharedHdrBuffer, // Sum of linear colors of still frames
     data: Arc<Mutex<SharedData>>,
     completion_callback: Arc<Mutex<Box<dyn Fn(Duration) -> () + Send + 'static>>>,
+    frame_callback: Mutex<Option<FrameCallback>>,
+    time_provider: Mutex<Option<Box<dyn Fn() -> Duration + Send + 'static>>>,
+    span_buffers: Mutex<Vec<SpanBuffers>>, // Reused by render threads, one set per thread
+    first_pass_done: Condvar,              // Signaled when first_pass_threads reaches 0
 }
 
 impl Renderer {
@@ -111,6 +999,9 @@ impl Renderer {
         // Create two shared render buffers, wrapped in Arc and Mutex
         let buffer_0: SharedBuffer = Arc::new(Mutex::new(Vec::new()));
         let buffer_1: SharedBuffer = Arc::new(Mutex::new(Vec::new()));
+        let hdr_buffer_0: SharedHdrBuffer = Arc::new(Mutex::new(Vec::new()));
+        let hdr_buffer_1: SharedHdrBuffer = Arc::new(Mutex::new(Vec::new()));
+        let hit_id_buffer: SharedBuffer = Arc::new(Mutex::new(Vec::new()));
 
         // Empty callback closure on heap
         let empty_callback: Box<dyn Fn(Duration) + Send + 'static> = Box::new(|_| {});
@@ -120,30 +1011,109 @@ impl Renderer {
             width: 0,
             height: 0,
             // Dummy defaults, set later
-            scene: Scene {
-                camera: Camera {
-                    pos: [0.0, 0.0, 0.0],
-                    right: [1.0, 0.0, 0.0],
-                    up: [0.0, 1.0, 0.0],
-                    forward: [0.0, 0.0, 1.0],
-                },
-                spheres: Vec::new(),
-                lights: Vec::new(),
-                sky_color: (0.0, 0.0, 0.0),
+            scene_cache: SceneCache {
+                scene: Arc::new(Scene {
+                    camera: Camera {
+                        pos: [0.0, 0.0, 0.0],
+                        right: [1.0, 0.0, 0.0],
+                        up: [0.0, 1.0, 0.0],
+                        forward: [0.0, 0.0, 1.0],
+                        projection: Projection::Perspective,
+                    },
+                    spheres: Vec::new(),
+                    lights: Vec::new(),
+                    environment: Environment::Color(0.0, 0.0, 0.0),
+                    ambient_occlusion: Vec::new(),
+                }),
+                time_samples: Vec::new(),
+                populate: scene_juggler::populate_scene,
             },
+            camera_override: None,
+            max_spheres: None,
+            max_lights: None,
+            over_limits: false,
+            scene_invalid: false,
             buffer_0_active: true,
-            next_pixel: 0,
+            span_layout: SpanLayout {
+                width: 0,
+                height: 0,
+                span_size: RENDER_SPAN,
+                tile_size: None,
+            },
+            span_size: RENDER_SPAN,
+            tile_size: None,
+            next_span: 0,
+            next_edge_span: 0,
             num_pixels: 0,
             start_time: Instant::now(),
+            deadline: None,
+            skipped_spans: None,
             duration: Duration::ZERO,
             threads: vec![],
+            running_threads: 0,
+            first_pass_threads: 0,
+            cancelled: false,
+            num_threads: num_cpus::get(),
+            samples_per_axis: 1,
+            adaptive_supersampling: false,
+            coverage_antialiasing: false,
+            firefly_clamp: None,
+            min_screen_radius: None,
+            dithering: false,
+            gamma: 1.0,
+            clear_color: DEFAULT_COLOR,
+            outline: None,
+            focus_peaking: None,
+            bloom: None,
+            denoise: None,
+            artificial_delay: None,
+            accumulate: false,
+            accumulated_frames: 0,
+            accumulated_scene: None,
+            accumulated_exposure: 1.0,
+            ambient_occlusion: false,
+            ao_cache: Vec::new(),
+            time_jitter: None,
+            interleaved: false,
+            interleave_phase: 0,
+            exposure: 1.0,
+            auto_exposure: None,
+            adapted_exposure: 1.0,
+            adapted_at: None,
+            frame_exposure: 1.0,
+            trace_settings: TraceSettings {
+                render_mode: RenderMode::Shaded,
+                min_ray_weight: MIN_RAY_WEIGHT,
+                dark_reflection_cutoff: 0.0,
+                transparent_background: false,
+                transparent_reflections: false,
+                environment_in_reflections: false,
+                filter_textures: false,
+                recursion_depth: RECURSION_DEPTH,
+                emissive_lights: false,
+                shadow_tint: (0.0, 0.0, 0.0),
+                sky_rotation: 0.0,
+                background: None,
+                pixel_size: 0.0,
+                time: Duration::ZERO,
+            },
+            stats: RenderStats::default(),
+            callback_panic: None,
         }));
 
         Arc::new(Renderer {
             buffer_0,
             buffer_1,
+            hdr_buffer_0,
+            hdr_buffer_1,
+            hit_id_buffer,
+            accumulation_buffer: Arc::new(Mutex::new(Vec::new())),
             data,
             completion_callback: Arc::new(Mutex::new(empty_callback)),
+            frame_callback: Mutex::new(None),
+            time_provider: Mutex::new(None),
+            span_buffers: Mutex::new(Vec::new()),
+            first_pass_done: Condvar::new(),
         })
     }
 
@@ -159,11 +1129,69 @@ impl Renderer {
         }
     }
 
+    pub fn snapshot(self: &SharedRenderer) -> Vec<u32> {
+        // Deep copy of the latest completed frame. The buffer is picked and
+        // copied while holding the shared data lock, so that a buffer swap
+        // cannot happen in between, unlike with get_buffer().
+        let data = self.data.lock().unwrap();
+        let buffer = if data.buffer_0_active {
+            &self.buffer_1
+        } else {
+            &self.buffer_0
+        };
+        let snapshot = buffer.lock().unwrap().clone();
+        snapshot
+    }
+
+    #[allow(dead_code)]
+    pub fn frame_hash(self: &SharedRenderer) -> u64 {
+        // Hash of the latest completed frame, for detecting changed renders.
+        // FNV-1a over the little-endian bytes of the size and the pixels,
+        // which is the same on every platform and Rust version, unlike
+        // DefaultHasher.
+        let data = self.data.lock().unwrap();
+        let buffer = if data.buffer_0_active {
+            self.buffer_1.lock().unwrap()
+        } else {
+            self.buffer_0.lock().unwrap()
+        };
+        let size = [data.width as u32, data.height as u32];
+        size.iter()
+            .chain(buffer.iter())
+            .flat_map(|value| value.to_le_bytes())
+            .fold(FNV_OFFSET_BASIS, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    pub fn get_hdr_buffer(self: &SharedRenderer) -> SharedHdrBuffer {
+        // Linear colors of the buffer returned by get_buffer()
+        let data = self.data.lock().unwrap();
+        if data.buffer_0_active {
+            Arc::clone(&self.hdr_buffer_1)
+        } else {
+            Arc::clone(&self.hdr_buffer_0)
+        }
+    }
+
     pub fn get_size(self: &SharedRenderer) -> (usize, usize) {
         let data = self.data.lock().unwrap();
         (data.width, data.height)
     }
 
+    #[allow(dead_code)]
+    pub fn set_clear_color(self: &SharedRenderer, color: (f64, f64, f64)) {
+        // Linear color of buffers before anything is rendered, also used by
+        // the window around the image. Set before set_size() to clear to it.
+        let mut data = self.data.lock().unwrap();
+        data.clear_color = color;
+    }
+
+    pub fn get_clear_color(self: &SharedRenderer) -> (f64, f64, f64) {
+        let data = self.data.lock().unwrap();
+        data.clear_color
+    }
+
     pub fn set_size(self: &SharedRenderer, size: (usize, usize)) {
         // Only square renders supported for now
         let (width, mut height) = size;
@@ -175,51 +1203,736 @@ impl Renderer {
         let mut data = self.data.lock().unwrap();
         let mut buffer_0 = self.buffer_0.lock().unwrap();
         let mut buffer_1 = self.buffer_1.lock().unwrap();
+        let mut hdr_buffer_0 = self.hdr_buffer_0.lock().unwrap();
+        let mut hdr_buffer_1 = self.hdr_buffer_1.lock().unwrap();
+        let mut hit_id_buffer = self.hit_id_buffer.lock().unwrap();
 
         // Set variables
+        let old_size = (data.width, data.height);
         data.width = width;
         data.height = height;
         data.num_pixels = width * height;
-        data.next_pixel = data.num_pixels; // End threads quickly
+        data.restart_accumulation();
+        data.next_span = usize::MAX; // End threads quickly
+        data.next_edge_span = usize::MAX;
+        data.cancelled = true;
 
-        // Resize buffers and clear them to a default color
-        buffer_0.clear();
-        buffer_0.resize(data.num_pixels, color_to_u32(DEFAULT_COLOR));
-        buffer_1.clear();
-        buffer_1.resize(data.num_pixels, color_to_u32(DEFAULT_COLOR));
+        if old_size.0 * old_size.1 > 0 && data.num_pixels > 0 {
+            // Scale the completed image to the new size in both buffers, to
+            // show until the next render completes
+            let (completed, completed_hdr) = if data.buffer_0_active {
+                (&buffer_1, &hdr_buffer_1)
+            } else {
+                (&buffer_0, &hdr_buffer_0)
+            };
+            let scaled = rescale(completed, old_size, (width, height));
+            let scaled_hdr = rescale(completed_hdr, old_size, (width, height));
+            buffer_0.clone_from(&scaled);
+            *buffer_1 = scaled;
+            hdr_buffer_0.clone_from(&scaled_hdr);
+            *hdr_buffer_1 = scaled_hdr;
+        } else {
+            // Resize buffers and clear them to the clear color
+            let clear_color = data.clear_color;
+            buffer_0.clear();
+            buffer_0.resize(data.num_pixels, color_to_u32(clear_color));
+            buffer_1.clear();
+            buffer_1.resize(data.num_pixels, color_to_u32(clear_color));
+            hdr_buffer_0.clear();
+            hdr_buffer_0.resize(data.num_pixels, clear_color);
+            hdr_buffer_1.clear();
+            hdr_buffer_1.resize(data.num_pixels, clear_color);
+        }
+        hit_id_buffer.clear();
+        hit_id_buffer.resize(data.num_pixels, NO_HIT);
     }
 
-    pub fn set_completion_callback<F>(self: &SharedRenderer, callback: F)
-    where
-        F: Fn(Duration) + Send + 'static,
-    {
-        let mut completion_callback = self.completion_callback.lock().unwrap();
-        *completion_callback = Box::new(callback);
+    pub fn warmup(self: &SharedRenderer) {
+        // Optional, call after set_size() so that the first render does not
+        // pay for faulting in freshly allocated buffers, e.g., when measuring
+        // render times. Render threads are started for each frame, so there
+        // is no thread pool to start here.
+        self.wait_for_completion(false);
+        touch_pages(&mut self.buffer_0.lock().unwrap());
+        touch_pages(&mut self.buffer_1.lock().unwrap());
+        touch_pages(&mut self.hdr_buffer_0.lock().unwrap());
+        touch_pages(&mut self.hdr_buffer_1.lock().unwrap());
+        touch_pages(&mut self.hit_id_buffer.lock().unwrap());
     }
 
-    pub fn start_render(
-        self: &SharedRenderer,
-        duration_since_start: Duration,
-        scene_options: &SceneOptions,
-    ) {
-        // First, wait for all threads to end
+    #[allow(dead_code)]
+    pub fn reset(self: &SharedRenderer) {
+        // Forget everything earlier renders left behind: images, accumulated
+        // frames, baked ambient occlusion, adapted exposure, stats and the
+        // camera override. Settings are kept. Waits for the render in
+        // progress, and leaves no threads running. After set_size(),
+        // set_scene() and reset(), the next render starts from a clean slate,
+        // as if by a new renderer with the same settings.
+        self.wait_for_completion(true);
+
+        let mut data = self.data.lock().unwrap();
+        let mut buffer_0 = self.buffer_0.lock().unwrap();
+        let mut buffer_1 = self.buffer_1.lock().unwrap();
+        let mut hdr_buffer_0 = self.hdr_buffer_0.lock().unwrap();
+        let mut hdr_buffer_1 = self.hdr_buffer_1.lock().unwrap();
+        let mut hit_id_buffer = self.hit_id_buffer.lock().unwrap();
+        let mut accumulation_buffer = self.accumulation_buffer.lock().unwrap();
+
+        let clear_color = data.clear_color;
+        buffer_0.fill(color_to_u32(clear_color));
+        buffer_1.fill(color_to_u32(clear_color));
+        hdr_buffer_0.fill(clear_color);
+        hdr_buffer_1.fill(clear_color);
+        hit_id_buffer.fill(NO_HIT);
+        accumulation_buffer.clear();
+
+        let (min_exposure, max_exposure) = EXPOSURE_RANGE;
+        data.camera_override = None;
+        data.scene_cache.time_samples.clear();
+        data.over_limits = false;
+        data.scene_invalid = false;
+        data.restart_accumulation();
+        data.accumulated_scene = None;
+        data.ao_cache.clear();
+        data.interleave_phase = 0;
+        data.adapted_exposure = data.exposure.clamp(min_exposure, max_exposure);
+        data.adapted_at = None;
+        data.duration = Duration::ZERO;
+        data.stats = RenderStats::default();
+    }
+
+    pub fn set_supersampling(self: &SharedRenderer, samples_per_axis: usize) {
+        // Takes effect on the next render
+        let mut data = self.data.lock().unwrap();
+        data.samples_per_axis = samples_per_axis.clamp(1, MAX_SAMPLES_PER_AXIS);
+        data.restart_accumulation();
+    }
+
+    pub fn get_supersampling(self: &SharedRenderer) -> usize {
+        let data = self.data.lock().unwrap();
+        data.samples_per_axis
+    }
+
+    #[allow(dead_code)]
+    pub fn set_recursion_depth(self: &SharedRenderer, recursion_depth: usize) {
+        // Bounces of reflection and see-through rays, 0: none. Takes effect
+        // on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.recursion_depth = recursion_depth;
+        data.restart_accumulation();
+    }
+
+    pub fn set_quality(self: &SharedRenderer, preset: QualityPreset) {
+        // Set supersampling, bounces, ambient occlusion and denoising in one
+        // go. Each remains adjustable on its own afterwards. There are no
+        // gamma, tone mapping or soft shadow settings to include yet.
+        let (samples_per_axis, adaptive, recursion_depth, ambient_occlusion, denoise) = match preset
+        {
+            QualityPreset::Draft => (1, false, 1, false, None),
+            QualityPreset::Balanced => (2, true, RECURSION_DEPTH, false, None),
+            QualityPreset::High => (4, true, 4, true, None),
+            QualityPreset::Ultra => (4, false, 6, true, Some(0.05)),
+        };
+        self.set_supersampling(samples_per_axis);
+        self.set_adaptive_supersampling(adaptive);
+        self.set_recursion_depth(recursion_depth);
+        self.set_ambient_occlusion(ambient_occlusion);
+        self.set_denoise(denoise);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_adaptive_supersampling(self: &SharedRenderer, adaptive: bool) {
+        // Supersample only pixels on geometry edges, others get one sample
+        let mut data = self.data.lock().unwrap();
+        data.adaptive_supersampling = adaptive;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_coverage_antialiasing(self: &SharedRenderer, enabled: bool) {
+        // Smooth silhouettes without supersampling: after rendering, blend
+        // pixels on sphere edges with a neighbor across the edge, in
+        // proportion to how much of the pixel the sphere in front covers.
+        // Only with one sample per pixel. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.coverage_antialiasing = enabled;
+        data.restart_accumulation();
+    }
+
+    pub fn set_num_threads(self: &SharedRenderer, num_threads: usize) {
+        // Takes effect on the next render, defaults to the number of logical CPUs
+        let mut data = self.data.lock().unwrap();
+        data.num_threads = num_threads.max(1);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_scene_limits(
+        self: &SharedRenderer,
+        max_spheres: Option<usize>,
+        max_lights: Option<usize>,
+    ) {
+        // Soft limits to catch runaway scene generation, None: unlimited
+        let mut data = self.data.lock().unwrap();
+        (data.max_spheres, data.max_lights) = (max_spheres, max_lights);
+    }
+
+    pub fn set_scene(self: &SharedRenderer, populate: PopulateScene) {
+        // Takes effect on the next render
+        let mut data = self.data.lock().unwrap();
+        data.scene_cache.populate = populate;
+        data.restart_accumulation();
+    }
+
+    pub fn get_camera(self: &SharedRenderer) -> Option<Camera> {
+        // Camera override, if any
+        let data = self.data.lock().unwrap();
+        data.camera_override.clone()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_active_camera(self: &SharedRenderer) -> Camera {
+        // Camera of the latest render: the override if set, otherwise the one
+        // the scene populated, e.g., the orbiting camera. To take control of
+        // it, nudge the returned camera and pass it to set_camera().
+        let data = self.data.lock().unwrap();
+        data.scene_cache.scene.camera.clone()
+    }
+
+    pub fn set_camera(self: &SharedRenderer, camera: Option<Camera>) {
+        // Render from this camera instead of the scene's, None: scene's camera.
+        // Persists across frames, as populating the scene does not replace
+        // it. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.camera_override = camera;
+    }
+
+    pub fn set_span_size(self: &SharedRenderer, span_size: usize) {
+        // Pixels that a render thread claims at a time, when not rendering in
+        // tiles. Smaller spans balance load better, larger ones lock less.
+        // Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.span_size = span_size.max(1);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_tile_size(self: &SharedRenderer, tile_size: Option<usize>) {
+        // Render in square tiles instead of row-major spans, better for
+        // memory locality and progressive display. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.tile_size = tile_size.map(|tile_size| tile_size.max(1));
+    }
+
+    #[allow(dead_code)]
+    pub fn set_render_mode(self: &SharedRenderer, render_mode: RenderMode) {
+        // Takes effect on the next render
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.render_mode = render_mode;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_accumulate(self: &SharedRenderer, accumulate: bool) {
+        // Average successive frames of an unchanging scene, to reduce noise
+        // of path tracing. Starts over when the scene or camera changes.
+        let mut data = self.data.lock().unwrap();
+        data.accumulate = accumulate;
+        data.restart_accumulation();
+    }
+
+    pub fn set_transparent_background(self: &SharedRenderer, transparent: bool) {
+        // Background seen directly, or through see-through spheres, gets
+        // alpha 0 instead of the environment color. Alpha is stored in the
+        // high byte of the buffer, with colors premultiplied by it. Takes
+        // effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.transparent_background = transparent;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_transparent_reflections(self: &SharedRenderer, transparent: bool) {
+        // With a transparent background, false: reflections show the
+        // environment (default), true: reflections of the background are
+        // transparent as well, so mirrors let the composited image through
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.transparent_reflections = transparent;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_ambient_occlusion(self: &SharedRenderer, ambient_occlusion: bool) {
+        // Darken ambient light on spheres surrounded by others. Baked once
+        // per sphere and reused while it stays in place, so that the mostly
+        // static juggler costs little per frame. Takes effect on the next
+        // render.
+        let mut data = self.data.lock().unwrap();
+        data.ambient_occlusion = ambient_occlusion;
+        data.ao_cache.clear();
+        data.restart_accumulation();
+        if !ambient_occlusion {
+            Arc::make_mut(&mut data.scene_cache.scene)
+                .ambient_occlusion
+                .clear();
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn set_environment_in_reflections(self: &SharedRenderer, environment: bool) {
+        // true: reflections of skip_lighting spheres (e.g., a sky sphere as
+        // backdrop) show the scene's environment instead, with its gradient,
+        // rather than a flat full-bright surface. The sphere itself looks the
+        // same when seen directly. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.environment_in_reflections = environment;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_texture_filtering(self: &SharedRenderer, filter: bool) {
+        // Average checker textures over the area a pixel covers, so that the
+        // distant ground fades to the mean of its colors instead of
+        // shimmering. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.filter_textures = filter;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_emissive_lights(self: &SharedRenderer, emissive_lights: bool) {
+        // Let spheres with emission light other surfaces and cast shadows
+        // like point lights, instead of only glowing themselves. Costs a
+        // shadow ray per emitter and hit. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.emissive_lights = emissive_lights;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_shadow_tint(self: &SharedRenderer, shadow_tint: (f64, f64, f64)) {
+        // Share of each light that still reaches points in its shadow, per
+        // channel, a cheap stand-in for light bounced into shadows. For
+        // example (0.15, 0.12, 0.2) gives faint bluish shadows, (0.0, 0.0, 0.0)
+        // leaves only ambient light. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.shadow_tint = shadow_tint;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_sky_rotation(self: &SharedRenderer, sky_rotation: f64) {
+        // Turn the environment around the Y axis, in radians, positive from
+        // +Z towards +X, moving what reflections pick up from it without
+        // moving the camera or lights. Vertical gradients look the same at any
+        // rotation. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.sky_rotation = sky_rotation;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_min_ray_weight(self: &SharedRenderer, min_ray_weight: f64) {
+        // Stop tracing reflections and see-through rays once their share of
+        // the pixel color falls below this, 0.0: trace to full recursion depth.
+        // Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.min_ray_weight = min_ray_weight.max(0.0);
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_dark_reflection_cutoff(self: &SharedRenderer, cutoff: f64) {
+        // Skip tracing a reflection when the environment in the reflected
+        // direction, times the reflection's share of the pixel, has less
+        // luminance than this, and use that environment color instead. Weak
+        // reflections of a dark environment are rarely worth a full trace.
+        // 0.0: trace all reflections, 1/255 is about one step of 8-bit color.
+        // Takes effect on the next render.
+        //
+        // The prediction sees only the environment, not the spheres in the
+        // way. A dark sphere against a bright sky is never skipped, and a
+        // bright sphere against a dark environment can be skipped wrongly,
+        // e.g., a lit floor reflected in a mirror under a night sky.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.dark_reflection_cutoff = cutoff.max(0.0);
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_firefly_clamp(self: &SharedRenderer, max: Option<f64>) {
+        // Limit luminance of each sample before averaging, None: no limit
+        let mut data = self.data.lock().unwrap();
+        data.firefly_clamp = max;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_min_screen_radius(self: &SharedRenderer, min_radius: Option<f64>) {
+        // Cull spheres with a smaller projected radius in pixels, None: no culling
+        let mut data = self.data.lock().unwrap();
+        data.min_screen_radius = min_radius;
+        data.restart_accumulation();
+    }
+
+    pub fn set_gamma(self: &SharedRenderer, gamma: f64) {
+        // Colors are raised to 1 / gamma before quantization, 2.2 for a
+        // typical display. Exposure and averaging stay linear.
+        let mut data = self.data.lock().unwrap();
+        data.gamma = gamma;
+        data.restart_accumulation();
+    }
+
+    pub fn get_gamma(self: &SharedRenderer) -> f64 {
+        let data = self.data.lock().unwrap();
+        data.gamma
+    }
+
+    #[allow(dead_code)]
+    pub fn set_dithering(self: &SharedRenderer, dithering: bool) {
+        // Ordered dithering hides banding in smooth gradients
+        let mut data = self.data.lock().unwrap();
+        data.dithering = dithering;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_exposure(self: &SharedRenderer, exposure: f64) {
+        // Scale of linear colors before quantization, 2.0: one stop brighter.
+        // Used while automatic exposure is off. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.exposure = exposure.max(0.0);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_auto_exposure(self: &SharedRenderer, enabled: bool, adaptation_speed: f64) {
+        // Adapt exposure towards mid gray average luminance of completed
+        // frames, ignoring the darkest and brightest pixels, within two stops
+        // of 1.0. Adaptation speed is per second, 1.0 closes about 63% of the
+        // gap in a second. Starts from the manual exposure. Takes effect on
+        // the next render.
+        let mut data = self.data.lock().unwrap();
+        let (min_exposure, max_exposure) = EXPOSURE_RANGE;
+        data.auto_exposure = enabled.then_some(adaptation_speed.max(0.0));
+        data.adapted_exposure = data.exposure.clamp(min_exposure, max_exposure);
+        data.adapted_at = None;
+        data.restart_accumulation();
+    }
+
+    pub fn set_time_jitter(self: &SharedRenderer, interval: Option<Duration>) {
+        // Spread supersamples of each pixel over this time after the frame's
+        // time, usually the frame interval, for a cheap motion blur instead
+        // of strobing. Populates the scene once more per extra time, at most
+        // TIME_SAMPLES times in all. Only with supersampling, and only for
+        // the supersampled edges with adaptive supersampling. None: off.
+        // Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.time_jitter = interval;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_interleaved(self: &SharedRenderer, interleaved: bool) {
+        // Render every other pixel in a checkerboard pattern, alternating
+        // between frames, and keep the other half from the previous frame.
+        // Almost doubles the frame rate, but anything that moves more than
+        // a pixel per frame shows a combed ghost of where it was. The first
+        // frame after a resize is half default color. Takes effect on the
+        // next render.
+        let mut data = self.data.lock().unwrap();
+        data.interleaved = interleaved;
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_outline(self: &SharedRenderer, outline: Option<Outline>) {
+        // Outline where the sphere hit by primary rays changes, None: no outline
+        let mut data = self.data.lock().unwrap();
+        data.outline = outline;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_focus_peaking(self: &SharedRenderer, focus_peaking: Option<FocusPeaking>) {
+        // Paint over surfaces that primary rays hit at about a distance, for
+        // seeing where a focal plane cuts the scene, None: no highlight
+        let mut data = self.data.lock().unwrap();
+        data.focus_peaking = focus_peaking;
+    }
+
+    pub fn set_artificial_delay(self: &SharedRenderer, delay: Option<Duration>) {
+        // Sleep before rendering each span, to test frame rate adaptation and
+        // deadlines without a slow computer. None: full speed. Takes effect
+        // on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.artificial_delay = delay;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_denoise(self: &SharedRenderer, strength: Option<f64>) {
+        // Smooth noise within areas of similar color on the same sphere,
+        // before bloom and outline. Strength is the color difference, in
+        // linear units, that is still smoothed over. None: no denoising.
+        let mut data = self.data.lock().unwrap();
+        data.denoise = strength;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_bloom(self: &SharedRenderer, bloom: Option<Bloom>) {
+        // Add blurred bright pixels to the image, None: no bloom
+        let mut data = self.data.lock().unwrap();
+        data.bloom = bloom;
+    }
+
+    // Called from a render thread when a render is completed. Panics in the
+    // callback are caught, logged and kept for take_callback_panic(), except
+    // in release builds, which abort on panic.
+    pub fn set_completion_callback<F>(self: &SharedRenderer, callback: F)
+    where
+        F: Fn(Duration) + Send + 'static,
+    {
+        let mut completion_callback = self.completion_callback.lock().unwrap();
+        *completion_callback = Box::new(callback);
+    }
+
+    // Message of the latest panic in the frame or completion callback, if
+    // any since the last call. A panicking callback does not fail the render
+    // or wait_for_completion(): the frame is complete, and later frames call
+    // the callback again. Callers that treat a callback panic as an error
+    // check here after waiting for completion.
+    #[allow(dead_code)]
+    pub fn take_callback_panic(self: &SharedRenderer) -> Option<String> {
+        let mut data = self.data.lock().unwrap();
+        data.callback_panic.take()
+    }
+
+    // Called from the render thread that completed a frame, before the
+    // completion callback, with the frame's pixels, width and height. The
+    // buffer stays locked during the call, so the callback should copy what
+    // it needs and return quickly, and must not call get_buffer() or
+    // snapshot(). Panics are handled as for the completion callback.
+    #[allow(dead_code)]
+    pub fn set_frame_callback<F>(self: &SharedRenderer, callback: F)
+    where
+        F: Fn(&[u32], usize, usize, Duration) + Send + 'static,
+    {
+        let mut frame_callback = self.frame_callback.lock().unwrap();
+        *frame_callback = Some(Box::new(callback));
+    }
+
+    // Computes the color of rays that miss every sphere, instead of the
+    // scene's environment, e.g., for animated gradients or starfields. It is
+    // called from all render threads for every missed ray, reflections
+    // included, so it should be quick; with primary_only, only rays from the
+    // camera use it and the rest see the environment. Takes effect on the
+    // next render.
+    #[allow(dead_code)]
+    pub fn set_background_callback<F>(self: &SharedRenderer, callback: F, primary_only: bool)
+    where
+        F: Fn(Vector3<f64>, Duration) -> (f64, f64, f64) + Send + Sync + 'static,
+    {
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.background = Some(Background {
+            callback: Arc::new(callback),
+            primary_only,
+        });
+        data.restart_accumulation();
+    }
+
+    #[allow(dead_code)]
+    pub fn clear_background_callback(self: &SharedRenderer) {
+        // Back to the scene's environment. Takes effect on the next render.
+        let mut data = self.data.lock().unwrap();
+        data.trace_settings.background = None;
+        data.restart_accumulation();
+    }
+
+    // Scene time from an external clock, e.g., the playback position of
+    // music, for scrubbing, looping or synchronizing the animation. The
+    // window asks for it every frame, and it takes precedence over the
+    // window's own time source, which keeps running underneath. Rendering
+    // to files ignores it and uses fixed time steps.
+    #[allow(dead_code)]
+    pub fn set_time_provider(self: &SharedRenderer, provider: Box<dyn Fn() -> Duration + Send>) {
+        *self.time_provider.lock().unwrap() = Some(provider);
+    }
+
+    #[allow(dead_code)]
+    pub fn clear_time_provider(self: &SharedRenderer) {
+        // Back to the window's own time source
+        *self.time_provider.lock().unwrap() = None;
+    }
+
+    pub fn provided_time(self: &SharedRenderer) -> Option<Duration> {
+        // Scene time from the time provider, None if there is none
+        self.time_provider
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|provider| provider())
+    }
+
+    pub fn start_render(
+        self: &SharedRenderer,
+        duration_since_start: Duration,
+        scene_options: &SceneOptions,
+    ) {
+        self.begin_render(duration_since_start, scene_options, None);
+    }
+
+    // Like start_render(), but render threads stop taking new spans once the
+    // budget has elapsed. Pixels not rendered by then keep their values from
+    // the previous frame, so the completed buffer may be partial. Edge pixels
+    // not yet refined by adaptive supersampling keep their single sample.
+    #[allow(dead_code)]
+    pub fn start_render_with_deadline(
+        self: &SharedRenderer,
+        duration_since_start: Duration,
+        scene_options: &SceneOptions,
+        budget: Duration,
+    ) {
+        self.begin_render(duration_since_start, scene_options, Some(budget));
+    }
+
+    fn begin_render(
+        self: &SharedRenderer,
+        duration_since_start: Duration,
+        scene_options: &SceneOptions,
+        budget: Option<Duration>,
+    ) {
+        // First, wait for all threads to end
         self.wait_for_completion(true);
 
         let mut data = self.data.lock().unwrap();
 
-        data.next_pixel = 0; // Start over
+        data.next_span = 0; // Start over
+        data.next_edge_span = 0;
+        data.span_layout = SpanLayout {
+            width: data.width,
+            height: data.height,
+            span_size: data.span_size,
+            tile_size: data.tile_size,
+        };
+        data.trace_settings.pixel_size = 1.0 / data.height.saturating_sub(1).max(1) as f64;
+        data.trace_settings.time = duration_since_start;
         data.start_time = Instant::now(); // Record start of render
+        data.deadline = budget.map(|budget| data.start_time + budget);
+        data.skipped_spans = None;
+        data.cancelled = false;
         data.duration = Duration::ZERO;
+        data.stats = RenderStats::default();
+        data.interleave_phase ^= 1; // Alternate the half of pixels, if interleaved
+        data.frame_exposure = if data.auto_exposure.is_some() {
+            data.adapted_exposure
+        } else {
+            data.exposure
+        };
 
         // Get a scene to render
-        populate_scene(&mut data.scene, duration_since_start, scene_options);
+        let camera_override = data.camera_override.clone();
+        data.scene_cache.update(
+            duration_since_start,
+            scene_options,
+            camera_override.as_ref(),
+        );
+
+        // Same scene at more times within the frame, if supersamples are
+        // spread over time
+        let num_samples = data.samples_per_axis * data.samples_per_axis;
+        let (interval, num_scenes) = match data.time_jitter {
+            Some(interval) if num_samples > 1 => (interval, TIME_SAMPLES.min(num_samples)),
+            _ => (Duration::ZERO, 1),
+        };
+        data.scene_cache.update_time_samples(
+            duration_since_start,
+            interval,
+            num_scenes,
+            scene_options,
+            camera_override.as_ref(),
+        );
+
+        // Warn once when the scene grows beyond limits
+        let (num_spheres, num_lights) = {
+            let scene = &data.scene_cache.scene;
+            (scene.spheres.len(), scene.lights.len())
+        };
+        let too_many_spheres = data.max_spheres.is_some_and(|max| num_spheres > max);
+        let too_many_lights = data.max_lights.is_some_and(|max| num_lights > max);
+        if (too_many_spheres || too_many_lights) && !data.over_limits {
+            log::warn!(
+                "Scene has {} spheres (limit {}) and {} lights (limit {})",
+                num_spheres,
+                data.max_spheres
+                    .map_or("none".to_string(), |max| max.to_string()),
+                num_lights,
+                data.max_lights
+                    .map_or("none".to_string(), |max| max.to_string()),
+            );
+        }
+        data.over_limits = too_many_spheres || too_many_lights;
+
+        // In debug builds, warn once about scene mistakes, until fixed
+        if cfg!(debug_assertions) {
+            let result = data.scene_cache.scene.validate();
+            if let Err(warnings) = &result {
+                if !data.scene_invalid {
+                    for warning in warnings {
+                        log::warn!("{warning}");
+                    }
+                }
+            }
+            data.scene_invalid = result.is_err();
+        }
+
+        // Skip spheres too small to be seen reliably at this resolution
+        if let Some(min_radius) = data.min_screen_radius {
+            let height = data.height;
+            data.scene_cache.cull_small_spheres(min_radius, height);
+        }
+
+        // Occlusion of ambient light, reusing values of spheres that barely
+        // moved. Scenes at jittered times use the same values, if they have
+        // the same spheres.
+        if data.ambient_occlusion {
+            let data = &mut *data;
+            data.scene_cache.bake_ambient_occlusion(&mut data.ao_cache);
+            let scene_cache = &mut data.scene_cache;
+            for time_sample in scene_cache.time_samples.iter_mut() {
+                let time_sample = Arc::make_mut(time_sample);
+                time_sample.ambient_occlusion.clear();
+                if time_sample.spheres.len() == scene_cache.scene.spheres.len() {
+                    time_sample
+                        .ambient_occlusion
+                        .extend_from_slice(&scene_cache.scene.ambient_occlusion);
+                }
+            }
+        }
+
+        // Start accumulating over whenever the scene, camera or exposure
+        // changes. Setters of other settings that change the image restart
+        // accumulation themselves.
+        if data.accumulate {
+            let unchanged = data.accumulated_scene.as_ref().is_some_and(|scene| {
+                Arc::ptr_eq(scene, &data.scene_cache.scene) || **scene == *data.scene_cache.scene
+            }) && data.accumulated_exposure == data.frame_exposure;
+            if !unchanged {
+                data.restart_accumulation();
+            }
+            data.accumulated_scene = Some(Arc::clone(&data.scene_cache.scene));
+            data.accumulated_exposure = data.frame_exposure;
+        }
 
-        // Start as many render threads as there are logical CPUs
-        for _ in 0..num_cpus::get() {
+        // Start render threads, by default as many as there are logical CPUs
+        let num_threads = data.num_threads;
+        data.running_threads = num_threads;
+        data.first_pass_threads = num_threads;
+        for n in 0..num_threads {
             let thread_self: SharedRenderer = Arc::clone(&self);
-            data.threads.push(thread::spawn(move || {
-                thread_self.thread_func();
-            }));
+            // Reuse the span buffers of an earlier frame's thread, if any
+            let span_buffers = self.span_buffers.lock().unwrap().pop().unwrap_or_default();
+            let thread = thread::Builder::new()
+                .name(format!("{WORKER_NAME}-{n}"))
+                .spawn(move || {
+                    thread_self.thread_func(span_buffers);
+                })
+                .unwrap();
+            data.threads.push(thread);
         }
     }
 
@@ -229,7 +1942,9 @@ impl Renderer {
 
         if flush {
             // Starting over, end threads quickly
-            data.next_pixel = data.num_pixels;
+            data.next_span = usize::MAX;
+            data.next_edge_span = usize::MAX;
+            data.cancelled = true;
         }
 
         // Atomically copy and clear thread IDs
@@ -238,10 +1953,41 @@ impl Renderer {
         // Release lock
         drop(data);
 
-        // Wait for all threads to be completed
+        // Wait for all threads to be completed, then pass on the first
+        // panic with its message
+        let mut first_panic = None;
         for thread in threads {
-            thread.join().unwrap();
+            let name = thread.thread().name().unwrap_or(WORKER_NAME).to_string();
+            if let Err(payload) = thread.join() {
+                first_panic.get_or_insert(format!("{name} panicked: {}", panic_message(&*payload)));
+            }
         }
+        if let Some(message) = first_panic {
+            panic!("{message}");
+        }
+    }
+
+    pub fn depth_pass(self: &SharedRenderer, size: (usize, usize)) -> Vec<f32> {
+        // Distance from the camera to what each pixel's primary ray hits, in
+        // the scene of the latest render, f32::MAX where nothing is hit.
+        // Intersects again, at any size, as rendering keeps no depths.
+        let scene = {
+            let data = self.data.lock().unwrap();
+            Arc::clone(&data.scene_cache.scene)
+        };
+        let (width, height) = size;
+        (0..width * height)
+            .map(|pixel| {
+                primary_hit_distance(&scene, (pixel % width, pixel / width), size)
+                    .map_or(f32::MAX, |distance| distance as f32)
+            })
+            .collect()
+    }
+
+    pub fn get_scene(self: &SharedRenderer) -> Scene {
+        // Scene of the latest render, matches get_buffer() after wait_for_completion()
+        let data = self.data.lock().unwrap();
+        Scene::clone(&data.scene_cache.scene)
     }
 
     pub fn get_duration(self: &SharedRenderer) -> Duration {
@@ -249,82 +1995,142 @@ impl Renderer {
         return data.duration;
     }
 
-    fn thread_func(self: SharedRenderer) {
-        let mut span_buffer = vec![0x0000_0000; RENDER_SPAN];
+    pub fn get_stats(self: &SharedRenderer) -> RenderStats {
+        // Complete only after wait_for_completion()
+        let data = self.data.lock().unwrap();
+        data.stats
+    }
+
+    fn thread_func(self: SharedRenderer, span_buffers: SpanBuffers) {
+        // Tests count the allocations of this renderer's threads
+        #[cfg(test)]
+        tests::count_allocations_of(&self);
+
         let (width, height);
+        let span_layout;
         let scene;
+        let time_samples;
         let buffer_0_active;
+        let (samples_per_axis, firefly_clamp, adaptive);
+        let settings;
+        let (dithering, gamma);
+        let interleave;
+        let exposure;
+        let artificial_delay;
 
         {
             // Read shared data
             let data = self.data.lock().unwrap();
 
-            // Get render buffer width and height
+            // Get render buffer width and height, and how it is divided into spans
             (width, height) = (data.width, data.height);
+            span_layout = data.span_layout;
 
-            // Get thread local copies of scene elements (Camera, Spheres, Lights)
-            scene = data.scene.clone();
+            // Get a reference to the scene elements (Camera, Spheres, Lights)
+            scene = Arc::clone(&data.scene_cache.scene);
+            time_samples = data.scene_cache.time_samples.clone();
 
             // Get currently active buffer (i.e., the buffer to render)
             buffer_0_active = data.buffer_0_active;
+
+            // Get sampling settings
+            (samples_per_axis, firefly_clamp) = (data.samples_per_axis, data.firefly_clamp);
+            adaptive = data.adaptive_supersampling && samples_per_axis > 1;
+
+            // Get shading settings
+            settings = data.trace_settings.clone();
+            (dithering, gamma) = (data.dithering, data.gamma);
+            interleave = data.interleaved.then_some(data.interleave_phase);
+            exposure = data.frame_exposure;
+
+            // Get simulated slowness
+            artificial_delay = data.artificial_delay;
         }
 
-        let mut done = false;
-        while !done {
-            let pixel: usize; // Next buffer index to render
+        let render_pixel = |pixel: usize, samples_per_axis: usize| {
+            WORK_POSITION.with(|position| {
+                position.set(position.get().map(|(span, _)| (span, pixel)));
+            });
 
-            {
-                // Read/write shared data
-                let mut data = self.data.lock().unwrap();
-
-                // Get next pixel to render
-                pixel = data.next_pixel;
-                if pixel >= data.num_pixels {
-                    // All done, exit thread
-                    break;
-                }
+            // Get pixel coordinates x and y
+            let (x, y) = (pixel % width, pixel / width);
+            let (color, alpha, hit_id) = render_pixel(
+                &scene,
+                &time_samples,
+                &settings,
+                (x, y),
+                (width, height),
+                samples_per_axis,
+                firefly_clamp,
+            );
+            let color = (color.0 * exposure, color.1 * exposure, color.2 * exposure);
+            (color, alpha, hit_id)
+        };
 
-                // Update next pixel
-                data.next_pixel += RENDER_SPAN;
-                if data.next_pixel >= data.num_pixels {
-                    // When this last render span is finished, call completion callback
-                    data.next_pixel = data.num_pixels;
-                    done = true;
-                }
-            }
+        let quantize =
+            |pixel: usize, color: (f64, f64, f64)| quantize(pixel, color, width, dithering, gamma);
 
-            // TODO: Last span may be short. Currently, num_pixels must be
-            // divisible by RENDER_SPAN, otherwise there is an overflow
+        // Pixels of this frame's half of the checkerboard, all if not interleaved
+        let is_rendered =
+            |pixel: usize| interleave.is_none_or(|phase| is_interleaved_pixel(pixel, width, phase));
 
-            // Render a span of pixels
-            for n in 0..RENDER_SPAN {
-                // Get pixel coordinates x and y
-                let x = (pixel + n) % width;
-                let y = (pixel + n) / width;
-
-                // Scale x and y to viewport coordinates
-                let vx = (x as f64 / (width - 1) as f64) - 0.5;
-                let vy = 0.5 - (y as f64 / (height - 1) as f64);
-
-                // Set up camera and viewport for shooting rays
-                let ray_origin = scene.camera.pos;
-                let ray_dir = vec3_add(
-                    vec3_add(scene.camera.forward, vec3_scale(scene.camera.right, vx)),
-                    vec3_scale(scene.camera.up, vy),
-                );
+        // Span buffers of an earlier frame's thread, only allocated when the
+        // largest span grows
+        let SpanBuffers {
+            mut span_ranges,
+            mut span_buffer,
+            mut span_hdr_buffer,
+            mut span_hit_ids,
+            mut edge_pixels,
+        } = span_buffers;
+        let max_span_pixels = span_layout.max_span_pixels();
+        span_buffer.resize(max_span_pixels, 0);
+        span_hdr_buffer.resize(max_span_pixels, (0.0, 0.0, 0.0));
+        span_hit_ids.resize(max_span_pixels, NO_HIT);
+        if adaptive {
+            edge_pixels.reserve(max_span_pixels);
+        }
+
+        // A panic abandons the render, without leaving other threads waiting
+        // for this one to finish the first pass
+        let mut first_pass = FirstPassGuard {
+            renderer: &self,
+            done: false,
+        };
 
-                let t_min = vec3_len(ray_dir);
-                let t_max = f64::INFINITY;
-                let recursion_depth = 3;
+        // First pass: one sample per pixel if edges are refined later
+        let first_pass_samples = if adaptive { 1 } else { samples_per_axis };
+        let worker = thread::current().name().unwrap_or(WORKER_NAME).to_string();
+        while let Some(span) = self.claim_span(false) {
+            log::trace!("{worker}: rendering span {span}");
+            if let Some(delay) = artificial_delay {
+                // Simulate a slow computer
+                thread::sleep(delay);
+            }
 
-                // Trace a ray from the camera through the viewport
-                let color = trace_ray(&scene, ray_origin, ray_dir, t_min, t_max, recursion_depth);
+            span_layout.span_pixels(span, &mut span_ranges);
+            WORK_POSITION.with(|position| position.set(Some((span, 0))));
+            let len = span_ranges.iter().map(|range| range.len()).sum();
+            let span_buffer = &mut span_buffer[..len];
+            let span_hdr_buffer = &mut span_hdr_buffer[..len];
+            let span_hit_ids = &mut span_hit_ids[..len];
 
-                // Plot a pixel to span buffer
-                {
-                    let color = color_to_u32(color);
-                    span_buffer[n] = color;
+            if interleave.is_some() {
+                // Start from the previous frame, half of it is rendered over
+                self.copy_previous_frame(span..(span + 1));
+            }
+
+            // Render a span of pixels
+            for (n, pixel) in span_ranges.iter().cloned().flatten().enumerate() {
+                if !is_rendered(pixel) {
+                    continue;
                 }
+                let (color, alpha, hit_id) = render_pixel(pixel, first_pass_samples);
+
+                // Plot a pixel to span buffer
+                span_buffer[n] = set_alpha(quantize(pixel, color), alpha);
+                span_hdr_buffer[n] = color;
+                span_hit_ids[n] = hit_id;
             }
 
             {
@@ -335,34 +2141,1101 @@ impl Renderer {
                 } else {
                     shared_buffer = self.buffer_1.lock().unwrap()
                 }
-                let slice = &mut shared_buffer[pixel..(pixel + RENDER_SPAN)];
-                slice.copy_from_slice(&span_buffer);
+                copy_to_ranges(&mut shared_buffer, &span_ranges, span_buffer, is_rendered);
+            }
+
+            {
+                // Copy linear colors of the span to shared HDR buffer
+                let mut shared_hdr_buffer;
+                if buffer_0_active {
+                    shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap()
+                } else {
+                    shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap()
+                }
+                copy_to_ranges(
+                    &mut shared_hdr_buffer,
+                    &span_ranges,
+                    span_hdr_buffer,
+                    is_rendered,
+                );
             }
 
-            if done {
-                // This thread completed the render
-                let duration;
+            {
+                // Copy hit IDs of the span as well
+                let mut hit_id_buffer = self.hit_id_buffer.lock().unwrap();
+                copy_to_ranges(&mut hit_id_buffer, &span_ranges, span_hit_ids, is_rendered);
+            }
+        }
+
+        first_pass.leave();
+        if adaptive {
+            // Wait for all threads to complete the first pass, so that
+            // hit IDs of all neighboring pixels are available
+            let data = self.data.lock().unwrap();
+            drop(
+                self.first_pass_done
+                    .wait_while(data, |data| data.first_pass_threads > 0)
+                    .unwrap(),
+            );
+
+            // Second pass: supersample pixels on geometry edges only
+            while let Some(span) = self.claim_span(true) {
+                log::trace!("{worker}: supersampling edges of span {span}");
+                span_layout.span_pixels(span, &mut span_ranges);
+                WORK_POSITION.with(|position| position.set(Some((span, 0))));
+                edge_pixels.clear();
 
                 {
-                    // Read/write shared data
-                    let mut data = self.data.lock().unwrap();
+                    // Find pixels whose hit ID differs from a neighbor's
+                    let hit_id_buffer = self.hit_id_buffer.lock().unwrap();
+                    for p in span_ranges.iter().cloned().flatten() {
+                        if is_rendered(p) && is_edge_pixel(&hit_id_buffer, p, (width, height)) {
+                            edge_pixels.push((p, (0.0, 0.0, 0.0), 1.0));
+                        }
+                    }
+                }
 
-                    // Swap buffers
-                    data.buffer_0_active = !data.buffer_0_active;
+                // Render edge pixels with full supersampling
+                for (p, color, alpha) in edge_pixels.iter_mut() {
+                    (*color, *alpha, _) = render_pixel(*p, samples_per_axis);
+                }
 
-                    // Record duration of render
-                    duration = Instant::now().duration_since(data.start_time);
-                    data.duration = duration;
+                {
+                    // Get write access to shared buffer and replace edge pixels
+                    let mut shared_buffer;
+                    if buffer_0_active {
+                        shared_buffer = self.buffer_0.lock().unwrap()
+                    } else {
+                        shared_buffer = self.buffer_1.lock().unwrap()
+                    }
+                    for &(p, color, alpha) in edge_pixels.iter() {
+                        shared_buffer[p] = set_alpha(quantize(p, color), alpha);
+                    }
                 }
 
-                // Call completion callback
-                self.completion_callback.lock().unwrap()(duration);
+                {
+                    // Replace linear colors of edge pixels as well
+                    let mut shared_hdr_buffer;
+                    if buffer_0_active {
+                        shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap()
+                    } else {
+                        shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap()
+                    }
+                    for &(p, color, _) in edge_pixels.iter() {
+                        shared_hdr_buffer[p] = color;
+                    }
+                }
             }
         }
-    }
+
+        WORK_POSITION.with(|position| position.set(None));
+        drop(first_pass);
+
+        // Return the span buffers for the next frame
+        self.span_buffers.lock().unwrap().push(SpanBuffers {
+            span_ranges,
+            span_buffer,
+            span_hdr_buffer,
+            span_hit_ids,
+            edge_pixels,
+        });
+
+        // Add this thread's statistics to render statistics
+        let thread_stats = THREAD_STATS.with(|stats| stats.take());
+        let mut data = self.data.lock().unwrap();
+        data.stats.rays += thread_stats.rays;
+        data.stats.sphere_tests += thread_stats.sphere_tests;
+        data.stats.skipped_reflections += thread_stats.skipped_reflections;
+
+        // The last thread to finish completes the render, once no other
+        // thread is still copying a span to the buffers that post-processing
+        // reads and writes
+        data.running_threads -= 1;
+        if data.running_threads > 0 || data.cancelled {
+            return;
+        }
+        let num_spans = data.span_layout.num_spans();
+        let skipped_spans = data.skipped_spans.unwrap_or(num_spans)..num_spans;
+        drop(data);
+
+        // Fill pixels left unrendered in the first pass, if the deadline
+        // passed, from the previous frame
+        if !skipped_spans.is_empty() {
+            self.copy_previous_frame(skipped_spans);
+        }
+        self.complete_render();
+    }
+
+    fn claim_span(self: &SharedRenderer, second_pass: bool) -> Option<usize> {
+        // Read/write shared data
+        let mut data = self.data.lock().unwrap();
+        let (num_spans, deadline) = (data.span_layout.num_spans(), data.deadline);
+        let next_span = if second_pass {
+            &mut data.next_edge_span
+        } else {
+            &mut data.next_span
+        };
+
+        // Get next span to render
+        let span = *next_span;
+        if span >= num_spans {
+            // All done, exit thread
+            return None;
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            // Out of time, skip remaining spans of both passes. The last
+            // thread to finish completes the render.
+            data.skipped_spans = Some(if second_pass { num_spans } else { span });
+            data.next_span = num_spans;
+            data.next_edge_span = num_spans;
+            return None;
+        }
+
+        // Update next span
+        *next_span += 1;
+
+        Some(span)
+    }
+
+    fn copy_previous_frame(self: &SharedRenderer, spans: Range<usize>) {
+        let (buffer_0_active, span_layout) = {
+            let data = self.data.lock().unwrap();
+            (data.buffer_0_active, data.span_layout)
+        };
+
+        // Pixels of the spans
+        let mut pixels = Vec::new();
+        let mut span_ranges = Vec::new();
+        for span in spans {
+            span_layout.span_pixels(span, &mut span_ranges);
+            pixels.append(&mut span_ranges);
+        }
+
+        {
+            // Copy from the completed buffer to the one being rendered
+            let (mut active, previous);
+            if buffer_0_active {
+                (active, previous) = (self.buffer_0.lock().unwrap(), self.buffer_1.lock().unwrap());
+            } else {
+                (active, previous) = (self.buffer_1.lock().unwrap(), self.buffer_0.lock().unwrap());
+            }
+            for range in pixels.iter().cloned() {
+                active[range.clone()].copy_from_slice(&previous[range]);
+            }
+        }
+
+        {
+            // Copy linear colors as well
+            let (mut active, previous);
+            if buffer_0_active {
+                (active, previous) = (
+                    self.hdr_buffer_0.lock().unwrap(),
+                    self.hdr_buffer_1.lock().unwrap(),
+                );
+            } else {
+                (active, previous) = (
+                    self.hdr_buffer_1.lock().unwrap(),
+                    self.hdr_buffer_0.lock().unwrap(),
+                );
+            }
+            for range in pixels.iter().cloned() {
+                active[range.clone()].copy_from_slice(&previous[range]);
+            }
+        }
+    }
+
+    fn accumulate_frame(self: &SharedRenderer) {
+        // Add the completed frame to the sum of earlier ones, and replace it
+        // with the average
+        let (width, buffer_0_active, (dithering, gamma), frames) = {
+            let mut data = self.data.lock().unwrap();
+            data.accumulated_frames += 1;
+            (
+                data.width,
+                data.buffer_0_active,
+                (data.dithering, data.gamma),
+                data.accumulated_frames,
+            )
+        };
+
+        let (mut shared_buffer, mut shared_hdr_buffer);
+        if buffer_0_active {
+            shared_buffer = self.buffer_0.lock().unwrap();
+            shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap();
+        } else {
+            shared_buffer = self.buffer_1.lock().unwrap();
+            shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap();
+        }
+
+        let mut accumulation_buffer = self.accumulation_buffer.lock().unwrap();
+        if frames == 1 {
+            // First frame of a still scene, nothing to average yet
+            accumulation_buffer.clear();
+            accumulation_buffer.extend_from_slice(&shared_hdr_buffer);
+            return;
+        }
+
+        for (pixel, sum) in accumulation_buffer.iter_mut().enumerate() {
+            let color = shared_hdr_buffer[pixel];
+            *sum = (sum.0 + color.0, sum.1 + color.1, sum.2 + color.2);
+
+            let average = (
+                sum.0 / frames as f64,
+                sum.1 / frames as f64,
+                sum.2 / frames as f64,
+            );
+            shared_hdr_buffer[pixel] = average;
+            shared_buffer[pixel] = keep_alpha(
+                quantize(pixel, average, width, dithering, gamma),
+                shared_buffer[pixel],
+            );
+        }
+    }
+
+    fn apply_denoise(self: &SharedRenderer, strength: f64) {
+        // Bilateral filter: average nearby pixels weighted by distance and
+        // color similarity, only over pixels that hit the same sphere, so
+        // that silhouettes stay sharp
+        let (width, height, buffer_0_active, (dithering, gamma)) = {
+            let data = self.data.lock().unwrap();
+            (
+                data.width,
+                data.height,
+                data.buffer_0_active,
+                (data.dithering, data.gamma),
+            )
+        };
+
+        let (mut shared_buffer, mut shared_hdr_buffer);
+        if buffer_0_active {
+            shared_buffer = self.buffer_0.lock().unwrap();
+            shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap();
+        } else {
+            shared_buffer = self.buffer_1.lock().unwrap();
+            shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap();
+        }
+        let hit_ids = self.hit_id_buffer.lock().unwrap();
+
+        let radius = DENOISE_RADIUS as isize;
+        let spatial_sigma = DENOISE_RADIUS as f64 / 2.0;
+        let range_sigma = strength.max(f64::EPSILON);
+
+        let source = shared_hdr_buffer.clone();
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let p = y as usize * width + x as usize;
+                let (center, hit_id) = (source[p], hit_ids[p]);
+                let (mut r, mut g, mut b, mut weight_sum) = (0.0, 0.0, 0.0, 0.0);
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                            continue;
+                        }
+                        let n = ny as usize * width + nx as usize;
+                        if hit_ids[n] != hit_id {
+                            continue;
+                        }
+
+                        let color = source[n];
+                        let distance_sq = (dx * dx + dy * dy) as f64;
+                        let difference_sq = (color.0 - center.0).powi(2)
+                            + (color.1 - center.1).powi(2)
+                            + (color.2 - center.2).powi(2);
+                        let weight = (-distance_sq / (2.0 * spatial_sigma * spatial_sigma)
+                            - difference_sq / (2.0 * range_sigma * range_sigma))
+                            .exp();
+                        r += color.0 * weight;
+                        g += color.1 * weight;
+                        b += color.2 * weight;
+                        weight_sum += weight;
+                    }
+                }
+
+                // The center pixel always contributes, so weight_sum > 0
+                let color = (r / weight_sum, g / weight_sum, b / weight_sum);
+                shared_hdr_buffer[p] = color;
+                shared_buffer[p] = keep_alpha(
+                    quantize(p, color, width, dithering, gamma),
+                    shared_buffer[p],
+                );
+            }
+        }
+    }
+
+    fn adapt_exposure(self: &SharedRenderer, adaptation_speed: f64) {
+        let (buffer_0_active, frame_exposure) = {
+            let data = self.data.lock().unwrap();
+            (data.buffer_0_active, data.frame_exposure)
+        };
+
+        let average_log2 = {
+            let shared_hdr_buffer = if buffer_0_active {
+                self.hdr_buffer_0.lock().unwrap()
+            } else {
+                self.hdr_buffer_1.lock().unwrap()
+            };
+
+            // Histogram of log2 luminance, before exposure
+            let (min_log2, max_log2) = EXPOSURE_LOG2_RANGE;
+            let bin_width = (max_log2 - min_log2) / EXPOSURE_BINS as f64;
+            let mut histogram = [0usize; EXPOSURE_BINS];
+            for &color in shared_hdr_buffer.iter() {
+                let luminance = 0.2126 * color.0 + 0.7152 * color.1 + 0.0722 * color.2;
+                let log2 = (luminance / frame_exposure.max(f64::MIN_POSITIVE)).log2();
+                let bin = ((log2 - min_log2) / bin_width).clamp(0.0, (EXPOSURE_BINS - 1) as f64);
+                histogram[bin as usize] += 1;
+            }
+
+            // Average of bin centers, without the darkest and brightest pixels
+            let num_pixels = shared_hdr_buffer.len();
+            let (skip, keep) = (
+                (num_pixels as f64 * EXPOSURE_TRIM) as usize,
+                (num_pixels as f64 * (1.0 - 2.0 * EXPOSURE_TRIM)) as usize,
+            );
+            let (mut seen, mut sum, mut count) = (0, 0.0, 0);
+            for (bin, &bin_count) in histogram.iter().enumerate() {
+                // Pixels of this bin within the kept range
+                let start = seen.max(skip);
+                let end = (seen + bin_count).min(skip + keep);
+                seen += bin_count;
+                if end > start {
+                    sum += (min_log2 + (bin as f64 + 0.5) * bin_width) * (end - start) as f64;
+                    count += end - start;
+                }
+            }
+            if count == 0 {
+                return;
+            }
+            sum / count as f64
+        };
+
+        // Move exposure towards the target gradually, in stops, by time since
+        // the previous frame
+        let mut data = self.data.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = data
+            .adapted_at
+            .map_or(0.0, |adapted_at| (now - adapted_at).as_secs_f64());
+        data.adapted_at = Some(now);
+
+        let (min_exposure, max_exposure) = EXPOSURE_RANGE;
+        let target =
+            (EXPOSURE_KEY.log2() - average_log2).clamp(min_exposure.log2(), max_exposure.log2());
+        let current = data.adapted_exposure.log2();
+        let blend = 1.0 - (-adaptation_speed * elapsed).exp();
+        data.adapted_exposure = (current + (target - current) * blend).exp2();
+    }
+
+    fn apply_bloom(self: &SharedRenderer, bloom: &Bloom) {
+        let (width, height, buffer_0_active, (dithering, gamma)) = {
+            let data = self.data.lock().unwrap();
+            (
+                data.width,
+                data.height,
+                data.buffer_0_active,
+                (data.dithering, data.gamma),
+            )
+        };
+
+        let mut shared_hdr_buffer;
+        if buffer_0_active {
+            shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap()
+        } else {
+            shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap()
+        }
+
+        // Keep the part of bright pixels above threshold
+        let mut bright: Vec<(f64, f64, f64)> = shared_hdr_buffer
+            .iter()
+            .map(|&color| {
+                let luminance = 0.2126 * color.0 + 0.7152 * color.1 + 0.0722 * color.2;
+                if luminance > bloom.threshold {
+                    let scale = (luminance - bloom.threshold) / luminance;
+                    (color.0 * scale, color.1 * scale, color.2 * scale)
+                } else {
+                    (0.0, 0.0, 0.0)
+                }
+            })
+            .collect();
+
+        // Separable Gaussian blur, horizontally and then vertically
+        let sigma = (BLOOM_SIGMA * height as f64).max(1.0);
+        let radius = (3.0 * sigma).ceil() as isize;
+        let weights: Vec<f64> = (-radius..=radius)
+            .map(|n| (-((n * n) as f64) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let weight_sum: f64 = weights.iter().sum();
+
+        let blur = |source: &[(f64, f64, f64)], horizontal: bool| {
+            let mut target = vec![(0.0, 0.0, 0.0); source.len()];
+            for y in 0..height as isize {
+                for x in 0..width as isize {
+                    let (mut r, mut g, mut b) = (0.0, 0.0, 0.0);
+                    for (n, weight) in (-radius..=radius).zip(weights.iter()) {
+                        // Clamp to image edges
+                        let (sx, sy) = if horizontal { (x + n, y) } else { (x, y + n) };
+                        let sx = sx.clamp(0, width as isize - 1) as usize;
+                        let sy = sy.clamp(0, height as isize - 1) as usize;
+                        let color = source[sy * width + sx];
+                        r += color.0 * weight;
+                        g += color.1 * weight;
+                        b += color.2 * weight;
+                    }
+                    target[y as usize * width + x as usize] =
+                        (r / weight_sum, g / weight_sum, b / weight_sum);
+                }
+            }
+            target
+        };
+        bright = blur(&blur(&bright, true), false);
+
+        // Add glow to linear colors, then convert to the displayed buffer
+        let mut shared_buffer;
+        if buffer_0_active {
+            shared_buffer = self.buffer_0.lock().unwrap()
+        } else {
+            shared_buffer = self.buffer_1.lock().unwrap()
+        }
+        for (p, glow) in bright.into_iter().enumerate() {
+            let color = &mut shared_hdr_buffer[p];
+            color.0 += glow.0 * bloom.intensity;
+            color.1 += glow.1 * bloom.intensity;
+            color.2 += glow.2 * bloom.intensity;
+            shared_buffer[p] = keep_alpha(
+                quantize(p, *color, width, dithering, gamma),
+                shared_buffer[p],
+            );
+        }
+    }
+
+    fn apply_coverage_antialiasing(self: &SharedRenderer) {
+        // Estimate how much of each edge pixel the sphere in front covers,
+        // from the distance of the pixel corners to its silhouette, and blend
+        // the pixel with a neighbor across the edge by that share. Only edge
+        // pixels are visited, a few corner rays each.
+        let (width, height, buffer_0_active, (dithering, gamma), scene) = {
+            let data = self.data.lock().unwrap();
+            (
+                data.width,
+                data.height,
+                data.buffer_0_active,
+                (data.dithering, data.gamma),
+                Arc::clone(&data.scene_cache.scene),
+            )
+        };
+
+        let (mut shared_buffer, mut shared_hdr_buffer);
+        if buffer_0_active {
+            shared_buffer = self.buffer_0.lock().unwrap();
+            shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap();
+        } else {
+            shared_buffer = self.buffer_1.lock().unwrap();
+            shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap();
+        }
+        let hit_ids = self.hit_id_buffer.lock().unwrap();
+
+        // Blend from unblended neighbors
+        let (source, source_buffer) = (shared_hdr_buffer.clone(), shared_buffer.clone());
+        let alpha_at = |p: usize| (source_buffer[p] >> 24) as f64 / 255.0;
+
+        // Spheres farther than this from the camera are behind
+        let nearest =
+            |sphere: &Sphere| vec3_len(vec3_sub(sphere.pos, scene.camera.pos)) - sphere.r.abs();
+
+        for p in 0..width * height {
+            if !is_edge_pixel(&hit_ids, p, (width, height)) {
+                continue;
+            }
+
+            // First neighbor across the edge
+            let (x, y, hit_id) = (p % width, p / width, hit_ids[p]);
+            let neighbors = [
+                (x > 0).then(|| p - 1),
+                (x + 1 < width).then(|| p + 1),
+                (y > 0).then(|| p - width),
+                (y + 1 < height).then(|| p + width),
+            ];
+            let Some(q) = neighbors
+                .into_iter()
+                .flatten()
+                .find(|&q| hit_ids[q] != hit_id)
+            else {
+                continue;
+            };
+
+            // Sphere whose silhouette makes the edge: the pixel not showing
+            // it sees past it. Edges where spheres intersect have none.
+            let center = |p: usize| ((p % width) as f64, (p / width) as f64);
+            let silhouette = |(id, other): (u32, usize)| {
+                let sphere = scene.spheres.get(id as usize)?;
+                let outside =
+                    silhouette_distance(&scene.camera, sphere, center(other), (width, height));
+                (nearest(sphere) > 0.0 && outside > 0.0).then_some((id, sphere))
+            };
+            let Some((front_id, front)) = [(hit_id, q), (hit_ids[q], p)]
+                .into_iter()
+                .filter_map(silhouette)
+                .min_by(|(_, a), (_, b)| nearest(a).total_cmp(&nearest(b)))
+            else {
+                continue;
+            };
+
+            // Signed distance field across the pixel, linear between corners
+            let corner = |dx: f64, dy: f64| {
+                silhouette_distance(
+                    &scene.camera,
+                    front,
+                    (x as f64 + dx, y as f64 + dy),
+                    (width, height),
+                )
+            };
+            let (d00, d10, d01, d11) = (
+                corner(-0.5, -0.5),
+                corner(0.5, -0.5),
+                corner(-0.5, 0.5),
+                corner(0.5, 0.5),
+            );
+            let (gradient_x, gradient_y) =
+                ((d10 + d11 - d00 - d01) / 2.0, (d01 + d11 - d00 - d10) / 2.0);
+            let ramp = gradient_x.abs() + gradient_y.abs(); // Change of distance across the pixel
+            if ramp <= 0.0 {
+                continue;
+            }
+            let coverage = (0.5 - (d00 + d10 + d01 + d11) / 4.0 / ramp).clamp(0.0, 1.0);
+
+            // Keep the pixel on the side of the edge its center sample is on
+            let (front_p, back_p, coverage) = if front_id == hit_id {
+                (p, q, coverage.max(0.5))
+            } else {
+                (q, p, coverage.min(0.5))
+            };
+            let blend = |front: f64, back: f64| front * coverage + back * (1.0 - coverage);
+            let (front_color, back_color) = (source[front_p], source[back_p]);
+            let color = (
+                blend(front_color.0, back_color.0),
+                blend(front_color.1, back_color.1),
+                blend(front_color.2, back_color.2),
+            );
+            let alpha = blend(alpha_at(front_p), alpha_at(back_p));
+            shared_hdr_buffer[p] = color;
+            shared_buffer[p] = set_alpha(quantize(p, color, width, dithering, gamma), alpha);
+        }
+    }
+
+    fn draw_focus_peaking(self: &SharedRenderer, focus_peaking: &FocusPeaking) {
+        let (width, height, buffer_0_active, scene) = {
+            let data = self.data.lock().unwrap();
+            (
+                data.width,
+                data.height,
+                data.buffer_0_active,
+                Arc::clone(&data.scene_cache.scene),
+            )
+        };
+
+        // Find pixels whose primary ray hits within tolerance of the distance,
+        // intersecting again as only hit IDs are kept from rendering
+        let mut peak_pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let distance = primary_hit_distance(&scene, (x, y), (width, height));
+                if distance.is_some_and(|distance| {
+                    (distance - focus_peaking.distance).abs() <= focus_peaking.tolerance
+                }) {
+                    peak_pixels.push(y * width + x);
+                }
+            }
+        }
+
+        {
+            // Draw highlight to the buffer being completed
+            let mut shared_buffer;
+            if buffer_0_active {
+                shared_buffer = self.buffer_0.lock().unwrap()
+            } else {
+                shared_buffer = self.buffer_1.lock().unwrap()
+            }
+            for &p in peak_pixels.iter() {
+                shared_buffer[p] = color_to_u32(focus_peaking.color);
+            }
+        }
+
+        {
+            // Linear colors as well
+            let mut shared_hdr_buffer;
+            if buffer_0_active {
+                shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap()
+            } else {
+                shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap()
+            }
+            for &p in peak_pixels.iter() {
+                shared_hdr_buffer[p] = focus_peaking.color;
+            }
+        }
+    }
+
+    fn draw_outline(self: &SharedRenderer, outline: &Outline) {
+        let (width, height, buffer_0_active) = {
+            let data = self.data.lock().unwrap();
+            (data.width, data.height, data.buffer_0_active)
+        };
+
+        // Find pixels with a different hit ID within outline width
+        let radius = outline.width as isize;
+        let mut outline_pixels = Vec::new();
+        {
+            let hit_ids = self.hit_id_buffer.lock().unwrap();
+            for y in 0..height as isize {
+                for x in 0..width as isize {
+                    let hit_id = hit_ids[y as usize * width + x as usize];
+                    let on_edge = (-radius..=radius).any(|dy| {
+                        (-radius..=radius).any(|dx| {
+                            let (nx, ny) = (x + dx, y + dy);
+                            nx >= 0
+                                && ny >= 0
+                                && nx < width as isize
+                                && ny < height as isize
+                                && hit_ids[ny as usize * width + nx as usize] != hit_id
+                        })
+                    });
+                    if on_edge {
+                        outline_pixels.push(y as usize * width + x as usize);
+                    }
+                }
+            }
+        }
+
+        {
+            // Draw outline to the buffer being completed
+            let mut shared_buffer;
+            if buffer_0_active {
+                shared_buffer = self.buffer_0.lock().unwrap()
+            } else {
+                shared_buffer = self.buffer_1.lock().unwrap()
+            }
+            for &p in outline_pixels.iter() {
+                shared_buffer[p] = color_to_u32(outline.color);
+            }
+        }
+
+        {
+            // Linear colors as well
+            let mut shared_hdr_buffer;
+            if buffer_0_active {
+                shared_hdr_buffer = self.hdr_buffer_0.lock().unwrap()
+            } else {
+                shared_hdr_buffer = self.hdr_buffer_1.lock().unwrap()
+            }
+            for &p in outline_pixels.iter() {
+                shared_hdr_buffer[p] = outline.color;
+            }
+        }
+    }
+
+    fn complete_render(self: &SharedRenderer) {
+        let duration;
+        let (width, height, completed_buffer);
+
+        // Post-process the completed image
+        let (
+            coverage_antialiasing,
+            accumulate,
+            denoise,
+            bloom,
+            outline,
+            focus_peaking,
+            auto_exposure,
+        ) = {
+            let data = self.data.lock().unwrap();
+            (
+                // Supersampled renders have smooth edges already
+                data.coverage_antialiasing && data.samples_per_axis == 1,
+                data.accumulate,
+                data.denoise,
+                data.bloom,
+                data.outline,
+                data.focus_peaking,
+                data.auto_exposure,
+            )
+        };
+        if let Some(adaptation_speed) = auto_exposure {
+            // Measured before post-processing, which may add light
+            self.adapt_exposure(adaptation_speed);
+        }
+        if coverage_antialiasing {
+            self.apply_coverage_antialiasing();
+        }
+        if accumulate {
+            self.accumulate_frame();
+        }
+        if let Some(strength) = denoise {
+            self.apply_denoise(strength);
+        }
+        if let Some(bloom) = bloom {
+            self.apply_bloom(&bloom);
+        }
+        if let Some(outline) = outline {
+            self.draw_outline(&outline);
+        }
+        if let Some(focus_peaking) = focus_peaking {
+            self.draw_focus_peaking(&focus_peaking);
+        }
+
+        {
+            // Read/write shared data
+            let mut data = self.data.lock().unwrap();
+
+            // Swap buffers
+            data.buffer_0_active = !data.buffer_0_active;
+            (width, height) = (data.width, data.height);
+            completed_buffer = if data.buffer_0_active {
+                Arc::clone(&self.buffer_1)
+            } else {
+                Arc::clone(&self.buffer_0)
+            };
+
+            // Record duration of render
+            duration = Instant::now().duration_since(data.start_time);
+            data.duration = duration;
+        }
+
+        // Pass the completed frame to the frame callback, if any
+        if let Some(frame_callback) = self.frame_callback.lock().unwrap().as_ref() {
+            let buffer = completed_buffer.lock().unwrap();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                frame_callback(&buffer, width, height, duration)
+            }));
+            if let Err(payload) = result {
+                let message = format!("Frame callback panicked: {}", panic_message(&*payload));
+                log::error!("{message}");
+                self.data.lock().unwrap().callback_panic = Some(message);
+            }
+        }
+
+        // Call completion callback. A panicking callback is reported instead
+        // of unwinding the render thread, which would make the panic resurface
+        // in wait_for_completion() with no hint of where it came from.
+        let completion_callback = self.completion_callback.lock().unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| completion_callback(duration)));
+        if let Err(payload) = result {
+            let message = format!("Completion callback panicked: {}", panic_message(&*payload));
+            log::error!("{message}");
+            self.data.lock().unwrap().callback_panic = Some(message);
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    // Panic payloads are usually string literals or formatted strings
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+// Counts a render thread out of the first pass, and abandons the render if
+// the thread panics
+struct FirstPassGuard<'a> {
+    renderer: &'a Renderer,
+    done: bool, // Counted out already
+}
+
+impl FirstPassGuard<'_> {
+    fn leave(&mut self) {
+        // Wake threads waiting for the second pass when this is the last one
+        let mut data = self
+            .renderer
+            .data
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !self.done {
+            self.done = true;
+            data.first_pass_threads -= 1;
+            self.renderer.first_pass_done.notify_all();
+        }
+    }
+}
+
+impl Drop for FirstPassGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // Other threads stop claiming spans, and the render is not
+            // completed. wait_for_completion() passes the panic on.
+            let mut data = self
+                .renderer
+                .data
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            data.next_span = usize::MAX;
+            data.next_edge_span = usize::MAX;
+            data.cancelled = true;
+            drop(data);
+        }
+        self.leave();
+    }
+}
+
+pub fn install_panic_hook() {
+    // Add the span and pixel to panic messages of render threads, then
+    // report as usual. Called once, by main().
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let thread = thread::current();
+        if let Some(name) = thread.name().filter(|name| name.starts_with(WORKER_NAME)) {
+            if let Some((span, pixel)) = WORK_POSITION.with(|position| position.get()) {
+                eprintln!("{name} was rendering span {span}, pixel {pixel}");
+            }
+        }
+    }));
+}
+
+fn equirectangular_dir(x: f64, y: f64, width: usize, height: usize) -> Vector3<f64> {
+    // Direction through a pixel of a panorama around the world Y axis, with
+    // longitude 0 towards +Z in the middle of the image. Pixel edges, not
+    // centers, are at the image borders, so that the left and right borders
+    // meet at longitude ±180° without a doubled column. The length makes a
+    // pixel span pixel_size viewport units, as for perspective.
+    let longitude = ((x + 0.5) / width as f64 - 0.5) * TAU;
+    let latitude = (0.5 - (y + 0.5) / height as f64) * PI;
+    let length = 1.0 / PI;
+    [
+        length * latitude.cos() * longitude.sin(),
+        length * latitude.sin(),
+        length * latitude.cos() * longitude.cos(),
+    ]
+}
+
+fn touch_pages<T: Copy>(buffer: &mut [T]) {
+    // Write one value per memory page, unchanged, so that the page is mapped
+    let step = (PAGE_SIZE / std::mem::size_of::<T>().max(1)).max(1);
+    for value in buffer.iter_mut().step_by(step) {
+        *value = std::hint::black_box(*value);
+    }
+}
+
+fn rescale<T: Copy>(source: &[T], old_size: (usize, usize), new_size: (usize, usize)) -> Vec<T> {
+    // Nearest neighbor scaling
+    let ((old_width, old_height), (new_width, new_height)) = (old_size, new_size);
+    let mut target = Vec::with_capacity(new_width * new_height);
+    for y in 0..new_height {
+        let source_y = y * old_height / new_height;
+        for x in 0..new_width {
+            let source_x = x * old_width / new_width;
+            target.push(source[source_y * old_width + source_x]);
+        }
+    }
+    target
+}
+
+fn primary_hit_distance(scene: &Scene, pos: (usize, usize), size: (usize, usize)) -> Option<f64> {
+    // Distance from the camera to what the ray through a pixel center hits
+    let (x, y) = pos;
+    let (width, height) = size;
+    let (ray_origin, ray_dir) = scene.camera.primary_ray(x as f64, y as f64, width, height);
+    let t_min = vec3_len(ray_dir); // As in render_pixel()
+    let (closest_sphere, closest_t) =
+        intersect_ray_closest_sphere(scene, ray_origin, ray_dir, t_min, f64::INFINITY);
+    closest_sphere.map(|_| closest_t * vec3_len(ray_dir))
+}
+
+fn silhouette_distance(
+    camera: &Camera,
+    sphere: &Sphere,
+    pos: (f64, f64), // Pixel coordinates, pixel centers at whole numbers
+    size: (usize, usize),
+) -> f64 {
+    // Distance from the sphere's surface to the closest point of the ray
+    // through a position, negative when the ray passes through the sphere
+    let (ray_origin, ray_dir) = camera.primary_ray(pos.0, pos.1, size.0, size.1);
+    let to_center = vec3_sub(sphere.pos, ray_origin);
+    let along = vec3_dot(to_center, vec3_normalized(ray_dir));
+    let closest_sq = vec3_dot(to_center, to_center) - along * along;
+    closest_sq.max(0.0).sqrt() - sphere.r.abs()
+}
+
+fn copy_to_ranges<T: Copy>(
+    target: &mut [T],
+    ranges: &[Range<usize>],
+    source: &[T],
+    is_rendered: impl Fn(usize) -> bool,
+) {
+    // Copy consecutive source values to the pixel ranges of a span, only
+    // those of rendered pixels
+    let mut offset = 0;
+    for range in ranges {
+        let len = range.len();
+        let source = &source[offset..(offset + len)];
+        if range.clone().all(&is_rendered) {
+            target[range.clone()].copy_from_slice(source);
+        } else {
+            for (pixel, &value) in range.clone().zip(source) {
+                if is_rendered(pixel) {
+                    target[pixel] = value;
+                }
+            }
+        }
+        offset += len;
+    }
+}
+
+fn is_interleaved_pixel(pixel: usize, width: usize, phase: usize) -> bool {
+    // Pixels of one half of a checkerboard, phase 0 or 1 selects which
+    let (x, y) = (pixel % width, pixel / width);
+    (x + y + phase).is_multiple_of(2)
+}
+
+fn render_pixel(
+    scene: &Scene,
+    time_samples: &[Arc<Scene>], // Scene later in the frame, supersamples take turns
+    settings: &TraceSettings,
+    pos: (usize, usize),
+    size: (usize, usize),
+    samples_per_axis: usize,
+    firefly_clamp: Option<f64>,
+) -> ((f64, f64, f64), f64, u32) {
+    let (x, y) = pos;
+    let (width, height) = size;
+
+    // Supersamples take turns between the scene at the frame's time and later
+    // times, starting from a random one so that neighboring pixels differ
+    let num_scenes = if samples_per_axis > 1 {
+        time_samples.len() + 1
+    } else {
+        1 // One sample, at the frame's time
+    };
+    let first_scene = if num_scenes > 1 {
+        (random_f64() * num_scenes as f64) as usize
+    } else {
+        0
+    };
+
+    // Supersample the pixel on a regular grid of samples
+    let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+    let mut pixel_hit_id = NO_HIT;
+    for sy in 0..samples_per_axis {
+        for sx in 0..samples_per_axis {
+            let scene = match (first_scene + sy * samples_per_axis + sx) % num_scenes {
+                0 => scene,
+                n => &time_samples[n - 1],
+            };
+
+            // Sub-pixel offsets, centered on the pixel
+            let dx = (sx as f64 + 0.5) / samples_per_axis as f64 - 0.5;
+            let dy = (sy as f64 + 0.5) / samples_per_axis as f64 - 0.5;
+
+            // Ray from the camera through the sample position
+            let (ray_origin, ray_dir) =
+                scene
+                    .camera
+                    .primary_ray(x as f64 + dx, y as f64 + dy, width, height);
+
+            let t_min = vec3_len(ray_dir);
+            let t_max = f64::INFINITY;
+            let recursion_depth = settings.recursion_depth;
+
+            // Footprint of a sample grows with distance, from a point at the camera
+            let spread = settings.pixel_size / samples_per_axis as f64 / vec3_len(ray_dir);
+
+            // Trace a ray from the camera through the viewport
+            let (mut sample, alpha, hit_id) = trace_ray(
+                scene,
+                settings,
+                ray_origin,
+                ray_dir,
+                t_min,
+                t_max,
+                recursion_depth,
+                1.0,
+                settings.transparent_background,
+                (0.0, spread),
+            );
+
+            // Tame overly bright samples ("fireflies")
+            if let Some(max) = firefly_clamp {
+                sample = clamp_luminance(sample, max);
+            }
+
+            r += sample.0;
+            g += sample.1;
+            b += sample.2;
+            a += alpha;
+
+            // Pixel's hit ID is that of the sample closest to its center
+            if sx == samples_per_axis / 2 && sy == samples_per_axis / 2 {
+                pixel_hit_id = hit_id;
+            }
+        }
+    }
+
+    // Average the samples
+    let num_samples = (samples_per_axis * samples_per_axis) as f64;
+    (
+        (r / num_samples, g / num_samples, b / num_samples),
+        a / num_samples,
+        pixel_hit_id,
+    )
+}
+
+fn is_edge_pixel(hit_ids: &[u32], pixel: usize, size: (usize, usize)) -> bool {
+    let (width, height) = size;
+    let (x, y) = (pixel % width, pixel / width);
+    let hit_id = hit_ids[pixel];
+
+    // Compare to the four direct neighbors
+    (x > 0 && hit_ids[pixel - 1] != hit_id)
+        || (x + 1 < width && hit_ids[pixel + 1] != hit_id)
+        || (y > 0 && hit_ids[pixel - width] != hit_id)
+        || (y + 1 < height && hit_ids[pixel + width] != hit_id)
+}
+
+fn quantize(
+    pixel: usize,
+    color: (f64, f64, f64),
+    width: usize,
+    dithering: bool,
+    gamma: f64,
+) -> u32 {
+    let color = gamma_encode(color, gamma);
+    if dithering {
+        // Same offset for a pixel in every frame, to avoid flicker
+        let offset = dither_offset(pixel % width, pixel / width);
+        color_to_u32((color.0 + offset, color.1 + offset, color.2 + offset))
+    } else {
+        color_to_u32(color)
+    }
+}
+
+pub fn gamma_encode(color: (f64, f64, f64), gamma: f64) -> (f64, f64, f64) {
+    // Linear colors to display values, unchanged for gamma 1.0
+    if gamma == 1.0 {
+        return color;
+    }
+    let encode = |c: f64| c.max(0.0).powf(1.0 / gamma);
+    (encode(color.0), encode(color.1), encode(color.2))
+}
+
+fn dither_offset(x: usize, y: usize) -> f64 {
+    // Added to encoded colors, less than one 8-bit step. As color_to_u32()
+    // truncates, this rounds on average instead of biasing towards dark.
+    (BAYER_4X4[y % 4][x % 4] as f64 + 0.5) / 16.0 / 255.0
+}
+
+fn set_alpha(color: u32, alpha: f64) -> u32 {
+    // Alpha in the high byte, 255: opaque
+    color & 0x00ff_ffff | ((255.0 * alpha.clamp(0.0, 1.0)).round() as u32) << 24
+}
+
+fn keep_alpha(color: u32, previous: u32) -> u32 {
+    // Post-processing changes colors, not coverage
+    color & 0x00ff_ffff | previous & 0xff00_0000
 }
 
-fn color_to_u32(color: (f64, f64, f64)) -> u32 {
+pub fn color_to_u32(color: (f64, f64, f64)) -> u32 {
+    // Opaque, alpha 255 in the high byte
     let (mut r, mut g, mut b) = color;
 
     if r > 1.0 {
@@ -375,40 +3248,95 @@ fn color_to_u32(color: (f64, f64, f64)) -> u32 {
         b = 1.0
     };
 
-    ((255.0 * r) as u32) << 16 | ((255.0 * g) as u32) << 8 | ((255.0 * b) as u32)
+    0xff00_0000 | ((255.0 * r) as u32) << 16 | ((255.0 * g) as u32) << 8 | ((255.0 * b) as u32)
+}
+
+fn clamp_luminance(color: (f64, f64, f64), max: f64) -> (f64, f64, f64) {
+    let (r, g, b) = color;
+
+    // Relative luminance (Rec. 709)
+    let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    if luminance <= max {
+        return color;
+    }
+
+    // Scale down, preserving hue
+    let scale = max / luminance;
+    (r * scale, g * scale, b * scale)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn trace_ray(
     scene: &Scene,
+    settings: &TraceSettings,
     ray_origin: Vector3<f64>,
     ray_dir: Vector3<f64>,
     t_min: f64,
     t_max: f64,
     recursion_depth: usize,
-) -> (f64, f64, f64) {
-    if false {
-        // DEBUG: Simulate a slow computer
-        thread::sleep(Duration::from_millis(1));
-    }
-
+    weight: f64,            // Share of the pixel color that this ray contributes
+    transparent_miss: bool, // Background is transparent, otherwise environment
+    cone: (f64, f64),       // Footprint width at ray origin, growth per unit distance
+) -> ((f64, f64, f64), f64, u32) {
+    // Returns color premultiplied by alpha, alpha and hit ID
     let (closest_sphere, closest_t) =
         intersect_ray_closest_sphere(scene, ray_origin, ray_dir, t_min, t_max);
 
-    if let Some(sphere) = closest_sphere {
+    if let Some((sphere_id, sphere)) = closest_sphere {
         // Ray hit a sphere, calculate hit position and normal
         let hit_pos: Vector3<f64> = vec3_add(ray_origin, vec3_scale(ray_dir, closest_t));
-        let hit_normal: Vector3<f64> = vec3_normalized(vec3_sub(hit_pos, sphere.pos));
+        // Normal of a sphere with negative radius points inward
+        let mut hit_normal: Vector3<f64> =
+            vec3_normalized(vec3_scale(vec3_sub(hit_pos, sphere.pos), sphere.r.signum()));
+
+        // Flip normal to face the incoming ray (e.g., inside of the ground sphere)
+        if !sphere.material.one_sided && vec3_dot(hit_normal, ray_dir) > 0.0 {
+            hit_normal = vec3_scale(hit_normal, -1.0);
+        }
+
+        // Shadow catcher: show the environment behind the surface, darkened
+        // by the shadows falling on it. Other objects behind it are not seen.
+        // With a transparent background, the shadow is black with the
+        // shadow coverage as alpha, which composites to the same darkening.
+        if sphere.material.shadow_catcher {
+            let coverage = shadow_coverage(scene, hit_pos, hit_normal);
+            if transparent_miss {
+                return ((0.0, 0.0, 0.0), coverage, sphere_id as u32);
+            }
+            let primary = recursion_depth == settings.recursion_depth;
+            let (r, g, b) = background_color(scene, settings, ray_dir, primary);
+            let lit = 1.0 - coverage;
+            return ((r * lit, g * lit, b * lit), 1.0, sphere_id as u32);
+        }
 
         // Sum light intensities at hit position, taking normal into account
-        let intensity = if sphere.skip_lighting {
+        let mut intensity = if sphere.material.skip_lighting {
             // Full brightness (e.g., sky sphere)
-            1.0
+            (1.0, 1.0, 1.0)
         } else {
-            compute_lighting(scene, ray_dir, hit_pos, hit_normal, sphere.specular)
+            compute_lighting(
+                scene,
+                settings,
+                ray_dir,
+                hit_pos,
+                hit_normal,
+                sphere.material.specular,
+                scene
+                    .ambient_occlusion
+                    .get(sphere_id)
+                    .copied()
+                    .unwrap_or(1.0),
+            )
         };
 
+        // Width of the ray's footprint where it hits, stretched at grazing angles
+        let cos_theta = (-vec3_dot(ray_dir, hit_normal) / vec3_len(ray_dir)).clamp(0.0, 1.0);
+        let distance = closest_t * vec3_len(ray_dir);
+        let footprint = cone.0 + cone.1 * distance;
+        let surface_footprint = footprint / cos_theta.max(0.05);
+
         // Get color from sphere texture
-        let (mut r, mut g, mut b) = match sphere.texture {
+        let (mut r, mut g, mut b) = match sphere.material.texture {
             // Solid color
             Texture::Color(r, g, b) => (r, g, b),
 
@@ -417,7 +3345,18 @@ fn trace_ray(
                 color1,
                 color2,
                 scale,
+                distance_scaling,
+            } if settings.filter_textures && surface_footprint > 0.0 => {
+                let scale = checker_scale(scale, distance_scaling, distance);
+                filtered_checker(hit_pos, surface_footprint, color1, color2, scale)
+            }
+            Texture::CheckerXZ {
+                color1,
+                color2,
+                scale,
+                distance_scaling,
             } => {
+                let scale = checker_scale(scale, distance_scaling, distance);
                 let scale_05x = scale / 2.0;
                 let scale_2x = scale * 2.0;
                 let (x, z) = (hit_pos[0] - scale_05x, hit_pos[2] - scale_05x);
@@ -430,49 +3369,355 @@ fn trace_ray(
                 }
             }
 
+            // Lines on X-Z plane, for judging positions, always filtered as
+            // thin lines shimmer even more than checkers
+            Texture::Grid {
+                line_color,
+                bg_color,
+                spacing,
+                line_width,
+            } => grid_color(
+                hit_pos,
+                surface_footprint,
+                line_color,
+                bg_color,
+                spacing,
+                line_width,
+            ),
+
             // Vertical gradient (e.g., sky sphere)
             Texture::GradientY { color1, color2 } => {
-                let mut y = (hit_pos[1] - sphere.pos[1]) / sphere.r;
-
-                if y > 1.0 {
-                    y = 1.0;
-                } else if y < -1.0 {
-                    y = -1.0;
-                }
+                gradient_color(hit_pos, sphere, [0.0, 1.0, 0.0], color1, color2)
+            }
 
-                let ny = 1.0 - y;
+            // Gradient along an arbitrary axis
+            Texture::Gradient {
+                color1,
+                color2,
+                axis,
+            } => gradient_color(hit_pos, sphere, vec3_normalized(axis), color1, color2),
+        };
 
-                (
-                    color1.0 * y + color2.0 * ny,
-                    color1.1 * y + color2.1 * ny,
-                    color1.2 * y + color2.2 * ny,
-                )
+        // Path tracing: add light bounced off other surfaces, sampled with one
+        // cosine-weighted ray. Russian roulette ends paths by surface color,
+        // and the surviving paths are weighted up to compensate.
+        if settings.render_mode == RenderMode::PathTrace
+            && !sphere.material.skip_lighting
+            && recursion_depth > 0
+        {
+            let survival = r.max(g).max(b).clamp(0.05, 1.0);
+            if random_f64() < survival {
+                let bounce_dir = cosine_weighted_direction(hit_normal);
+                let ((in_r, in_g, in_b), _, _) = trace_ray(
+                    scene,
+                    settings,
+                    hit_pos,
+                    bounce_dir,
+                    RENDER_EPSILON,
+                    f64::INFINITY,
+                    recursion_depth - 1,
+                    weight * survival,
+                    false, // Light from the environment
+                    (footprint, cone.1),
+                );
+                intensity.0 += in_r / survival;
+                intensity.1 += in_g / survival;
+                intensity.2 += in_b / survival;
             }
-        };
+        }
+
+        // Apply total light intensity to texture color, per channel
+        let base_color = (r, g, b);
+        (r, g, b) = (r * intensity.0, g * intensity.1, b * intensity.2);
 
-        // Apply total light intensity to texture color
-        (r, g, b) = (r * intensity, g * intensity, b * intensity);
+        // Emitted light does not depend on lighting
+        let emission = sphere.material.emission;
+        (r, g, b) = (r + emission.0, g + emission.1, b + emission.2);
 
-        // Calculate reflections
-        let reflective = sphere.reflective;
-        if recursion_depth > 0 && reflective > 0.0 {
+        // Calculate reflections. Dielectrics reflect more at grazing angles
+        // (Schlick's approximation of Fresnel), with reflective as the
+        // reflectance head-on. Metals reflect the same at all angles.
+        let metallic = sphere.material.metallic;
+        let mut reflective = sphere.material.reflective;
+        if reflective > 0.0 {
+            let fresnel = reflective + (1.0 - reflective) * (1.0 - cos_theta).powi(5);
+            reflective = fresnel * (1.0 - metallic) + reflective * metallic;
+        }
+        let refl_weight = weight * reflective;
+        let mut alpha = 1.0;
+        if recursion_depth > 0 && reflective > 0.0 && refl_weight >= settings.min_ray_weight {
             let (t_min, t_max) = (RENDER_EPSILON, f64::INFINITY);
 
-            // Calculate reflection recursively
-            let refl_dir = reflect_ray(vec3_scale(ray_dir, -1.0), hit_normal);
-            let (refl_r, refl_g, refl_b) =
-                trace_ray(scene, hit_pos, refl_dir, t_min, t_max, recursion_depth - 1);
+            // Calculate reflection recursively, off the waves if rippled
+            let refl_normal = match sphere.material.ripple {
+                Some(ripple) => ripple_normal(hit_normal, hit_pos, &ripple),
+                None => hit_normal,
+            };
+            let refl_dir = reflect_ray(vec3_scale(ray_dir, -1.0), refl_normal);
+
+            // Predict the reflected light from the environment in that
+            // direction, and settle for the prediction if it adds too little.
+            // Spheres in that direction are not considered, see
+            // set_dark_reflection_cutoff(). Not with a transparent background
+            // in reflections, as the prediction has no alpha.
+            let cutoff = settings.dark_reflection_cutoff;
+            let dark_prediction = (cutoff > 0.0
+                && !(transparent_miss && settings.transparent_reflections))
+                .then(|| background_color(scene, settings, refl_dir, false))
+                .filter(|&(r, g, b)| refl_weight * (0.2126 * r + 0.7152 * g + 0.0722 * b) < cutoff);
+
+            let ((mut refl_r, mut refl_g, mut refl_b), mut refl_alpha, refl_hit_id) =
+                if let Some(predicted) = dark_prediction {
+                    THREAD_STATS.with(|stats| {
+                        let mut thread_stats = stats.get();
+                        thread_stats.skipped_reflections += 1;
+                        stats.set(thread_stats);
+                    });
+                    (predicted, 1.0, NO_HIT)
+                } else {
+                    trace_ray(
+                        scene,
+                        settings,
+                        hit_pos,
+                        refl_dir,
+                        t_min,
+                        t_max,
+                        recursion_depth - 1,
+                        refl_weight,
+                        transparent_miss && settings.transparent_reflections,
+                        (footprint, cone.1),
+                    )
+                };
+
+            // Reflected backdrop sphere replaced by the environment behind it
+            let reflected_sphere = scene.spheres.get(refl_hit_id as usize);
+            if settings.environment_in_reflections
+                && reflected_sphere.is_some_and(|sphere| sphere.material.skip_lighting)
+            {
+                ((refl_r, refl_g, refl_b), refl_alpha) =
+                    if transparent_miss && settings.transparent_reflections {
+                        ((0.0, 0.0, 0.0), 0.0)
+                    } else {
+                        (background_color(scene, settings, refl_dir, false), 1.0)
+                    };
+            }
+
+            // Metals tint reflections with their color, dielectrics do not
+            let tint = |base: f64| 1.0 - metallic + base * metallic;
+            let (refl_r, refl_g, refl_b) = (
+                refl_r * tint(base_color.0),
+                refl_g * tint(base_color.1),
+                refl_b * tint(base_color.2),
+            );
 
             // Mix object color and reflected color together in proportion
             r = r * (1.0 - reflective) + refl_r * reflective;
             g = g * (1.0 - reflective) + refl_g * reflective;
             b = b * (1.0 - reflective) + refl_b * reflective;
+            alpha = (1.0 - reflective) + refl_alpha * reflective;
+        }
+
+        // Blend in what is seen through the sphere, without refraction
+        let opacity = sphere.material.opacity;
+        let through_weight = weight * (1.0 - opacity);
+        if recursion_depth > 0 && opacity < 1.0 && through_weight >= settings.min_ray_weight {
+            let (t_min, t_max) = (RENDER_EPSILON, f64::INFINITY);
+
+            // Continue the ray straight through the hit position
+            let ((through_r, through_g, through_b), through_alpha, _) = trace_ray(
+                scene,
+                settings,
+                hit_pos,
+                ray_dir,
+                t_min,
+                t_max,
+                recursion_depth - 1,
+                through_weight,
+                transparent_miss,
+                (footprint, cone.1),
+            );
+
+            r = r * opacity + through_r * (1.0 - opacity);
+            g = g * opacity + through_g * (1.0 - opacity);
+            b = b * opacity + through_b * (1.0 - opacity);
+            alpha = alpha * opacity + through_alpha * (1.0 - opacity);
         }
 
-        (r, g, b)
+        ((r, g, b), alpha, sphere_id as u32)
+    } else if transparent_miss {
+        // Ray did not hit anything, nothing to cover the background with
+        ((0.0, 0.0, 0.0), 0.0, NO_HIT)
     } else {
         // Ray did not hit anything
-        scene.sky_color
+        let primary = recursion_depth == settings.recursion_depth;
+        (
+            background_color(scene, settings, ray_dir, primary),
+            1.0,
+            NO_HIT,
+        )
+    }
+}
+
+fn checker_scale(scale: f64, distance_scaling: bool, distance: f64) -> f64 {
+    // Optionally grow checker squares in power-of-two steps with distance,
+    // so that they keep roughly the same size on screen all the way to the
+    // horizon, instead of shrinking to below a pixel
+    if !distance_scaling || distance <= CHECKER_SCALING_DISTANCE {
+        return scale;
+    }
+    scale * (distance / CHECKER_SCALING_DISTANCE).log2().floor().exp2()
+}
+
+fn filtered_checker(
+    hit_pos: Vector3<f64>,
+    footprint: f64,
+    color1: (f64, f64, f64),
+    color2: (f64, f64, f64),
+    scale: f64,
+) -> (f64, f64, f64) {
+    // Checker pattern on X-Z plane, box filtered over a square footprint.
+    // Per axis, the square wave (+1 on even squares, -1 on odd) is averaged
+    // analytically from its integral, a triangle wave. The product of both
+    // axes gives the share of color2, which tends to one half far away.
+    let width = footprint / scale;
+    let filtered_wave = |coordinate: f64| {
+        let u = (coordinate - scale / 2.0) / scale; // In squares, as CheckerXZ
+        let integral = |u: f64| -2.0 * ((u / 2.0).rem_euclid(1.0) - 0.5).abs();
+        (integral(u + width / 2.0) - integral(u - width / 2.0)) / width
+    };
+    let share2 = 0.5 - 0.5 * filtered_wave(hit_pos[0]) * filtered_wave(hit_pos[2]);
+
+    (
+        color1.0 * (1.0 - share2) + color2.0 * share2,
+        color1.1 * (1.0 - share2) + color2.1 * share2,
+        color1.2 * (1.0 - share2) + color2.2 * share2,
+    )
+}
+
+fn grid_color(
+    hit_pos: Vector3<f64>,
+    footprint: f64,
+    line_color: (f64, f64, f64),
+    bg_color: (f64, f64, f64),
+    spacing: f64,
+    line_width: f64,
+) -> (f64, f64, f64) {
+    // Lines at multiples of spacing along X and Z, box filtered over a
+    // square footprint like filtered_checker(). Per axis, the share of the
+    // footprint on a line comes from the integral of the line pattern, a
+    // staircase with ramps. Far away, it tends to the share of area on lines.
+    let share = (line_width / spacing).clamp(0.0, 1.0); // Of a period, per axis
+    let width = footprint / spacing;
+    let on_line = |coordinate: f64| {
+        // Shifted so that a line spans the first share of each period
+        let u = coordinate / spacing + share / 2.0;
+        let integral = |u: f64| u.floor() * share + u.rem_euclid(1.0).min(share);
+        if width > 0.0 {
+            (integral(u + width / 2.0) - integral(u - width / 2.0)) / width
+        } else {
+            (u.rem_euclid(1.0) < share) as u8 as f64
+        }
+    };
+    let (x, z) = (on_line(hit_pos[0]), on_line(hit_pos[2]));
+    let line = x + z - x * z; // On either line
+
+    (
+        bg_color.0 * (1.0 - line) + line_color.0 * line,
+        bg_color.1 * (1.0 - line) + line_color.1 * line,
+        bg_color.2 * (1.0 - line) + line_color.2 * line,
+    )
+}
+
+fn gradient_color(
+    hit_pos: Vector3<f64>,
+    sphere: &Sphere,
+    axis: Vector3<f64>,
+    color1: (f64, f64, f64),
+    color2: (f64, f64, f64),
+) -> (f64, f64, f64) {
+    // Position along the normalized axis, relative to sphere center and radius
+    let mut t = vec3_dot(vec3_sub(hit_pos, sphere.pos), axis) / sphere.r.abs();
+
+    if t > 1.0 {
+        t = 1.0;
+    } else if t < -1.0 {
+        t = -1.0;
+    }
+
+    let nt = 1.0 - t;
+
+    (
+        color1.0 * t + color2.0 * nt,
+        color1.1 * t + color2.1 * nt,
+        color1.2 * t + color2.2 * nt,
+    )
+}
+
+fn background_color(
+    scene: &Scene,
+    settings: &TraceSettings,
+    ray_dir: Vector3<f64>,
+    primary: bool, // Ray from the camera, not reflected or bounced
+) -> (f64, f64, f64) {
+    // Color of a ray that misses every sphere, from the background callback
+    // if there is one for this ray, otherwise from the environment
+    match &settings.background {
+        Some(background) if primary || !background.primary_only => {
+            (background.callback)(vec3_normalized(ray_dir), settings.time)
+        }
+        _ => environment_color(&scene.environment, ray_dir, settings.sky_rotation),
+    }
+}
+
+fn environment_color(
+    environment: &Environment,
+    ray_dir: Vector3<f64>,
+    sky_rotation: f64, // Yaw in radians, turning the environment around the Y axis
+) -> (f64, f64, f64) {
+    // Turn the ray the other way instead of the environment
+    let (sin, cos) = sky_rotation.sin_cos();
+    let ray_dir = [
+        ray_dir[0] * cos - ray_dir[2] * sin,
+        ray_dir[1],
+        ray_dir[0] * sin + ray_dir[2] * cos,
+    ];
+
+    match *environment {
+        // Solid color
+        Environment::Color(r, g, b) => (r, g, b),
+
+        // Vertical gradient, by elevation of the ray
+        Environment::GradientY { color1, color2 } => {
+            let y = vec3_normalized(ray_dir)[1];
+            let ny = 1.0 - y;
+
+            (
+                color1.0 * y + color2.0 * ny,
+                color1.1 * y + color2.1 * ny,
+                color1.2 * y + color2.2 * ny,
+            )
+        }
+
+        // Checker pattern by longitude and latitude of the ray
+        Environment::Checker {
+            color1,
+            color2,
+            scale,
+        } => {
+            let dir = vec3_normalized(ray_dir);
+            let longitude = dir[0].atan2(dir[2]) + PI; // 0..TAU
+            let latitude = dir[1].clamp(-1.0, 1.0).asin() + PI / 2.0; // 0..PI
+
+            // Round to a whole number of squares around, to avoid a seam
+            let squares_around = (TAU / scale).round().max(1.0);
+            let u = (longitude / TAU * squares_around) as i64;
+            let v = (latitude / scale) as i64;
+            if (u + v) % 2 == 0 {
+                color1
+            } else {
+                color2
+            }
+        }
     }
 }
 
@@ -482,22 +3727,29 @@ fn intersect_ray_closest_sphere(
     ray_dir: Vector3<f64>,
     t_min: f64,
     t_max: f64,
-) -> (Option<&Sphere>, f64) {
+) -> (Option<(usize, &Sphere)>, f64) {
+    THREAD_STATS.with(|stats| {
+        let mut thread_stats = stats.get();
+        thread_stats.rays += 1;
+        thread_stats.sphere_tests += scene.spheres.len() as u64;
+        stats.set(thread_stats);
+    });
+
     let mut closest_t: f64 = f64::INFINITY;
-    let mut closest_sphere: Option<&Sphere> = None;
+    let mut closest_sphere: Option<(usize, &Sphere)> = None;
 
     // See if ray hits any of the spheres
-    for sphere in &scene.spheres {
+    for (sphere_id, sphere) in scene.spheres.iter().enumerate() {
         let (t1, t2) = intersect_ray_sphere(ray_origin, ray_dir, sphere);
 
         if t1 >= t_min && t1 <= t_max && t1 < closest_t {
             closest_t = t1;
-            closest_sphere = Some(sphere);
+            closest_sphere = Some((sphere_id, sphere));
         }
 
         if t2 >= t_min && t2 <= t_max && t2 < closest_t {
             closest_t = t2;
-            closest_sphere = Some(sphere);
+            closest_sphere = Some((sphere_id, sphere));
         }
     }
 
@@ -530,70 +3782,744 @@ fn intersect_ray_sphere(
 
 fn compute_lighting(
     scene: &Scene,
+    settings: &TraceSettings,
     ray_dir: Vector3<f64>,
     hit_pos: Vector3<f64>,
     hit_normal: Vector3<f64>,
     specular: f64,
-) -> f64 {
-    let mut total_intensity = 0.0;
+    ambient_occlusion: f64, // Share of ambient light reaching the surface
+) -> (f64, f64, f64) {
+    // Light intensity per color channel
+    let mut total_intensity = (0.0, 0.0, 0.0);
+    let mut add_intensity = |intensity: (f64, f64, f64)| {
+        total_intensity.0 += intensity.0;
+        total_intensity.1 += intensity.1;
+        total_intensity.2 += intensity.2;
+    };
+
+    // Lighting terms to include
+    let mode = settings.render_mode;
+    let ambient = mode == RenderMode::Shaded;
+
+    // Light arriving from a direction, unless in shadow
+    let direct_light = |light_intensity: f64,
+                        light_color: (f64, f64, f64),
+                        light_dir: Vector3<f64>,
+                        t_max: f64| {
+        direct_lighting(
+            scene,
+            settings,
+            ray_dir,
+            hit_pos,
+            hit_normal,
+            specular,
+            light_intensity,
+            light_color,
+            light_dir,
+            t_max,
+        )
+    };
 
     // Iterate over lights in the scene and add their intensities together
     for light in &scene.lights {
-        let light_intensity;
-        let light_dir: Vector3<f64>;
-        let t_min = RENDER_EPSILON;
-        let t_max;
-
         match light {
             Light::Ambient { intensity } => {
                 // Ambient light is non-directional
-                total_intensity += intensity;
-                continue;
+                if ambient {
+                    let intensity = *intensity * ambient_occlusion;
+                    add_intensity((intensity, intensity, intensity));
+                }
+            }
+            Light::HemisphereAmbient {
+                sky_color,
+                ground_color,
+            } => {
+                // Non-directional, but blended by how much the normal points up
+                if ambient {
+                    let t = (hit_normal[1] / vec3_len(hit_normal) + 1.0) / 2.0;
+                    add_intensity((
+                        (sky_color.0 * t + ground_color.0 * (1.0 - t)) * ambient_occlusion,
+                        (sky_color.1 * t + ground_color.1 * (1.0 - t)) * ambient_occlusion,
+                        (sky_color.2 * t + ground_color.2 * (1.0 - t)) * ambient_occlusion,
+                    ));
+                }
             }
             Light::Point { intensity, pos } => {
-                light_intensity = *intensity;
-                light_dir = vec3_sub(*pos, hit_pos);
-                t_max = 1.0;
+                let light_dir = vec3_sub(*pos, hit_pos);
+                add_intensity(direct_light(*intensity, (1.0, 1.0, 1.0), light_dir, 1.0));
             }
-            Light::Directional { intensity, dir } => {
-                light_intensity = *intensity;
-                light_dir = *dir; // Just the light direction directly
-                t_max = f64::INFINITY;
+            Light::Directional {
+                intensity,
+                dir,
+                color,
+            } => {
+                // Just the light direction directly
+                add_intensity(direct_light(*intensity, *color, *dir, f64::INFINITY));
             }
         }
+    }
 
-        // Shadow check
-        let (shadow_sphere, _) =
-            intersect_ray_closest_sphere(scene, hit_pos, light_dir, t_min, t_max);
-        if let Some(_) = shadow_sphere {
-            // Sphere hit, so in shadow
-            continue;
+    // Emissive spheres light their surroundings like point lights at their
+    // centers, as bright as their emission at their surface and falling off
+    // with the square of distance beyond it
+    if settings.emissive_lights {
+        for sphere in &scene.spheres {
+            let emission = sphere.material.emission;
+            if emission == (0.0, 0.0, 0.0) {
+                continue;
+            }
+
+            let light_dir = vec3_sub(sphere.pos, hit_pos);
+            let distance = vec3_len(light_dir);
+            let r = sphere.r.abs();
+            if distance <= r {
+                continue; // On or inside the emitting sphere
+            }
+
+            // Shadow rays end at the emitter's surface, so that it does not
+            // shadow itself
+            let t_max = (distance - r) / distance - RENDER_EPSILON;
+            let falloff = (sphere.r / distance).powi(2);
+            add_intensity(direct_light(falloff, emission, light_dir, t_max));
         }
+    }
 
-        let n_dot_l = vec3_dot(hit_normal, light_dir);
+    total_intensity
+}
+
+#[allow(clippy::too_many_arguments)]
+fn direct_lighting(
+    scene: &Scene,
+    settings: &TraceSettings,
+    ray_dir: Vector3<f64>,
+    hit_pos: Vector3<f64>,
+    hit_normal: Vector3<f64>,
+    specular: f64,
+    light_intensity: f64,
+    light_color: (f64, f64, f64),
+    light_dir: Vector3<f64>,
+    t_max: f64, // Along light_dir, occluders further away do not shadow
+) -> (f64, f64, f64) {
+    let mut total_intensity = (0.0, 0.0, 0.0);
+    let mut add_intensity = |intensity: f64| {
+        total_intensity.0 += intensity * light_color.0;
+        total_intensity.1 += intensity * light_color.1;
+        total_intensity.2 += intensity * light_color.2;
+    };
+
+    // Lighting terms to include
+    let mode = settings.render_mode;
+    let diffuse = mode != RenderMode::SpecularOnly;
+    let specular_highlights = mode == RenderMode::Shaded || mode == RenderMode::SpecularOnly;
+
+    // Shadow check
+    let (shadow_sphere, _) =
+        intersect_ray_closest_sphere(scene, hit_pos, light_dir, RENDER_EPSILON, t_max);
+    if let Some(_) = shadow_sphere {
+        // Sphere hit, so in shadow, lifted by the shadow tint regardless of
+        // which way the surface faces
+        let tint = settings.shadow_tint;
+        return (
+            light_intensity * light_color.0 * tint.0,
+            light_intensity * light_color.1 * tint.1,
+            light_intensity * light_color.2 * tint.2,
+        );
+    }
+
+    let n_dot_l = vec3_dot(hit_normal, light_dir);
+
+    // Calculate direction-dependent intensity for diffuse lighting
+    if diffuse && n_dot_l > 0.0 {
+        let n_dot_l_norm = n_dot_l / (vec3_len(hit_normal) * vec3_len(light_dir));
+        add_intensity(light_intensity * n_dot_l_norm);
+    }
 
-        // Calculate direction-dependent intensity for diffuse lighting
-        if n_dot_l > 0.0 {
-            let n_dot_l_norm = n_dot_l / (vec3_len(hit_normal) * vec3_len(light_dir));
-            total_intensity += light_intensity * n_dot_l_norm;
+    // Calculate direction-dependent specular highlights
+    if specular_highlights && specular >= 0.0 {
+        let view_dir = vec3_scale(ray_dir, -1.0);
+        let reflection_dir = reflect_ray(light_dir, hit_normal);
+        let r_dot_v = vec3_dot(reflection_dir, view_dir);
+        if r_dot_v > 0.0 {
+            let r_dot_v_norm = r_dot_v / (vec3_len(reflection_dir) * vec3_len(ray_dir));
+            add_intensity(r_dot_v_norm.powf(specular));
         }
+    }
+
+    total_intensity
+}
 
-        // Calculate direction-dependent specular highlights
-        if specular >= 0.0 {
-            let view_dir = vec3_scale(ray_dir, -1.0);
-            let reflection_dir = reflect_ray(light_dir, hit_normal);
-            let r_dot_v = vec3_dot(reflection_dir, view_dir);
-            if r_dot_v > 0.0 {
-                let r_dot_v_norm = r_dot_v / (vec3_len(reflection_dir) * vec3_len(ray_dir));
-                total_intensity += r_dot_v_norm.powf(specular);
+fn sphere_ambient_occlusion(scene: &Scene, sphere: &Sphere) -> f64 {
+    // Share of short rays from points evenly spread over the sphere that
+    // escape without hitting another sphere within AO_DISTANCE
+    let mut unoccluded = 0;
+    for n in 0..AO_POINTS {
+        // Fibonacci sphere: evenly spaced points, without clustering at poles.
+        // With a negative radius, the point is opposite the normal, which
+        // then points inward from it, as it should.
+        let y = 1.0 - 2.0 * (n as f64 + 0.5) / AO_POINTS as f64;
+        let ring_radius = (1.0 - y * y).sqrt();
+        let angle = n as f64 * PI * (3.0 - 5.0_f64.sqrt());
+        let normal = [ring_radius * angle.cos(), y, ring_radius * angle.sin()];
+        let point = vec3_add(sphere.pos, vec3_scale(normal, sphere.r));
+
+        for _ in 0..AO_RAYS {
+            let dir = vec3_normalized(cosine_weighted_direction(normal));
+            let (occluder, _) =
+                intersect_ray_closest_sphere(scene, point, dir, RENDER_EPSILON, AO_DISTANCE);
+            if occluder.is_none() {
+                unoccluded += 1;
             }
         }
     }
 
-    total_intensity
+    unoccluded as f64 / (AO_POINTS * AO_RAYS) as f64
+}
+
+fn shadow_coverage(scene: &Scene, hit_pos: Vector3<f64>, hit_normal: Vector3<f64>) -> f64 {
+    // Share of the direct light that shadow rays find blocked, from 0.0 (fully
+    // lit) to 1.0 (fully in shadow). Each point and directional light facing
+    // the surface counts by its diffuse contribution, so a dim light's shadow
+    // is faint. Ambient light casts no shadows. Used as the opacity of a
+    // shadow catcher.
+    let mut blocked = 0.0;
+    let mut total = 0.0;
+    for light in &scene.lights {
+        let (light_intensity, light_dir, t_max) = match light {
+            Light::Point { intensity, pos } => (*intensity, vec3_sub(*pos, hit_pos), 1.0),
+            Light::Directional { intensity, dir, .. } => (*intensity, *dir, f64::INFINITY),
+            Light::Ambient { .. } | Light::HemisphereAmbient { .. } => continue,
+        };
+
+        let n_dot_l = vec3_dot(hit_normal, light_dir);
+        if n_dot_l <= 0.0 {
+            continue; // Facing away, the light does not reach the surface anyway
+        }
+        let weight = light_intensity * n_dot_l / (vec3_len(hit_normal) * vec3_len(light_dir));
+        total += weight;
+
+        let (shadow_sphere, _) =
+            intersect_ray_closest_sphere(scene, hit_pos, light_dir, RENDER_EPSILON, t_max);
+        if shadow_sphere.is_some() {
+            blocked += weight;
+        }
+    }
+
+    if total > 0.0 {
+        blocked / total
+    } else {
+        0.0
+    }
+}
+
+fn random_f64() -> f64 {
+    // Uniform in [0, 1), from a xorshift generator seeded once per thread
+    RNG_STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            x = RandomState::new().build_hasher().finish() | 1;
+        }
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+fn cosine_weighted_direction(normal: Vector3<f64>) -> Vector3<f64> {
+    // Random direction on the hemisphere around normal, more likely near the
+    // normal in proportion to the cosine, like light reaching a diffuse surface
+    let normal = vec3_normalized(normal);
+    let helper = if normal[0].abs() > 0.9 {
+        [0.0, 1.0, 0.0]
+    } else {
+        [1.0, 0.0, 0.0]
+    };
+    let tangent = vec3_normalized(vec3_cross(helper, normal));
+    let bitangent = vec3_cross(normal, tangent);
+
+    let (u1, u2) = (random_f64(), random_f64());
+    let radius = u1.sqrt();
+    let angle = TAU * u2;
+    vec3_add(
+        vec3_add(
+            vec3_scale(tangent, radius * angle.cos()),
+            vec3_scale(bitangent, radius * angle.sin()),
+        ),
+        vec3_scale(normal, (1.0 - u1).sqrt()),
+    )
+}
+
+fn ripple_normal(normal: Vector3<f64>, hit_pos: Vector3<f64>, ripple: &Ripple) -> Vector3<f64> {
+    // Tilt the normal against the slope of the waves, each wave has its own
+    // direction, relative length and speed
+    const WAVES: [([f64; 2], f64, f64); 3] = [
+        ([1.0, 0.0], 1.0, 1.0),   // Direction in X and Z, length, speed
+        ([0.6, 0.8], 0.7, 1.3),   // Shorter and faster
+        ([-0.8, 0.6], 0.45, 1.7), // Shorter and faster still
+    ];
+    let (mut slope_x, mut slope_z) = (0.0, 0.0);
+    for (dir, length, speed) in WAVES {
+        let k = TAU / (ripple.wavelength * length);
+        let along = dir[0] * hit_pos[0] + dir[1] * hit_pos[2];
+        let slope =
+            ripple.amplitude / WAVES.len() as f64 * (k * along + speed * ripple.phase).cos();
+        slope_x += slope * dir[0];
+        slope_z += slope * dir[1];
+    }
+    vec3_normalized(vec3_sub(normal, [slope_x, 0.0, slope_z]))
 }
 
 fn reflect_ray(ray: Vector3<f64>, normal: Vector3<f64>) -> Vector3<f64> {
     let n_dot_r = vec3_dot(normal, ray);
     return vec3_sub(vec3_scale(normal, 2.0 * n_dot_r), ray);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_test;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts allocations of one size in the render threads of one renderer,
+    // to tell its span buffers from the rest. Other tests running at the
+    // same time allocate on threads that are not counted.
+    struct CountingAllocator;
+
+    static COUNTED_RENDERER: AtomicUsize = AtomicUsize::new(0); // Address, 0: none
+    static COUNTED_SIZE: AtomicUsize = AtomicUsize::new(0);
+    static COUNTED_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        // Set in render threads of the counted renderer
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    pub(super) fn count_allocations_of(renderer: &SharedRenderer) {
+        let address = Arc::as_ptr(renderer) as usize;
+        COUNTING.with(|counting| counting.set(address == COUNTED_RENDERER.load(Ordering::Relaxed)));
+    }
+
+    fn count(size: usize) {
+        // Not while the thread's locals are being destroyed
+        let counting = COUNTING.try_with(Cell::get).unwrap_or(false);
+        if counting && size == COUNTED_SIZE.load(Ordering::Relaxed) {
+            COUNTED_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn render_test_scene(renderer: &SharedRenderer, size: usize) -> Vec<u32> {
+        // One sample per pixel and no dithering, so that renders are exact
+        renderer.set_scene(scene_test::populate_scene);
+        renderer.set_size((size, size));
+        renderer.set_supersampling(1);
+        renderer.set_dithering(false);
+        render_frame(renderer, Duration::ZERO)
+    }
+
+    fn render_frame(renderer: &SharedRenderer, duration: Duration) -> Vec<u32> {
+        // Render with the current settings and wait for the frame
+        renderer.start_render(duration, &SceneOptions::default());
+        renderer.wait_for_completion(false);
+        renderer.snapshot()
+    }
+
+    #[test]
+    fn post_processing_sees_every_span() {
+        // The outline is found from hit IDs of the whole frame and drawn over
+        // it, so no span may arrive after the render is completed
+        let render_outlined = |num_threads| {
+            let renderer = Renderer::new();
+            renderer.set_num_threads(num_threads);
+            renderer.set_span_size(16);
+            renderer.set_outline(Some(Outline {
+                width: 1,
+                color: (1.0, 0.0, 0.0),
+            }));
+            render_test_scene(&renderer, 64)
+        };
+        let expected = render_outlined(1);
+        for _ in 0..20 {
+            assert!(render_outlined(8) == expected);
+        }
+    }
+
+    #[test]
+    fn panicking_callback_is_reported() {
+        // The render completes, the panic is kept for the caller, and the
+        // next render works as usual
+        let renderer = Renderer::new();
+        renderer.set_completion_callback(|_| panic!("callback failed"));
+        render_test_scene(&renderer, 16);
+        let message = renderer.take_callback_panic().unwrap();
+        assert!(message.contains("callback failed"), "{message}");
+        assert_eq!(renderer.take_callback_panic(), None);
+
+        renderer.set_completion_callback(|_| {});
+        render_test_scene(&renderer, 16);
+        assert_eq!(renderer.take_callback_panic(), None);
+    }
+
+    #[test]
+    fn accumulation_restarts_on_changes() {
+        // Frames are averaged while nothing changes, and start over when the
+        // scene, its camera's projection or a shading setting changes
+        let renderer = Renderer::new();
+        renderer.set_accumulate(true);
+        render_test_scene(&renderer, 16);
+        let render = || {
+            render_frame(&renderer, Duration::ZERO);
+            renderer.data.lock().unwrap().accumulated_frames
+        };
+        assert_eq!(render(), 2);
+
+        renderer.set_sky_rotation(1.0);
+        assert_eq!(render(), 1);
+
+        let mut camera = renderer.get_active_camera();
+        camera.projection = Projection::Equirectangular;
+        renderer.set_camera(Some(camera));
+        assert_eq!(render(), 1);
+        assert_eq!(render(), 2);
+
+        renderer.set_exposure(2.0);
+        assert_eq!(render(), 1);
+    }
+
+    #[test]
+    fn gamma_brightens_midtones_only() {
+        // Black and white are kept, and gamma 1.0 writes linear colors
+        let linear = render_test_scene(&Renderer::new(), 16);
+        let renderer = Renderer::new();
+        renderer.set_gamma(1.0);
+        assert_eq!(render_test_scene(&renderer, 16), linear);
+
+        let half = (0.5, 0.25, 0.0);
+        let encoded = gamma_encode(half, 2.0);
+        assert!((encoded.0 - 0.5f64.sqrt()).abs() < 1e-12);
+        assert!((encoded.1 - 0.5).abs() < 1e-12);
+        assert_eq!(encoded.2, 0.0);
+        assert_eq!(gamma_encode((1.0, 1.0, 1.0), 2.2), (1.0, 1.0, 1.0));
+
+        renderer.set_gamma(2.2);
+        let encoded = render_test_scene(&renderer, 16);
+        for (linear, encoded) in linear.iter().zip(&encoded) {
+            for shift in [0, 8, 16] {
+                assert!((encoded >> shift & 0xff) >= (linear >> shift & 0xff));
+            }
+        }
+        assert_ne!(encoded, linear);
+    }
+
+    #[test]
+    fn primary_ray_spans_the_viewport() {
+        // Corner pixel centers are on the viewport corners, one unit in
+        // front of the camera, and the middle pixel looks straight ahead
+        let mut camera = Camera {
+            pos: [1.0, 2.0, -3.0],
+            right: [1.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            forward: [0.0, 0.0, 1.0],
+            projection: Projection::Perspective,
+        };
+        camera.look_at([-2.0, 0.5, 4.0]);
+        let expected = |vx: f64, vy: f64| {
+            vec3_add(
+                vec3_add(camera.forward, vec3_scale(camera.right, vx)),
+                vec3_scale(camera.up, vy),
+            )
+        };
+        let assert_ray = |(x, y): (f64, f64), (width, height), dir: Vector3<f64>| {
+            let (origin, ray_dir) = camera.primary_ray(x, y, width, height);
+            assert_eq!(origin, camera.pos);
+            assert!(vec3_len(vec3_sub(ray_dir, dir)) < 1e-12, "pixel ({x}, {y})");
+        };
+
+        assert_ray((0.0, 0.0), (9, 5), expected(-0.5, 0.5));
+        assert_ray((8.0, 0.0), (9, 5), expected(0.5, 0.5));
+        assert_ray((0.0, 4.0), (9, 5), expected(-0.5, -0.5));
+        assert_ray((8.0, 4.0), (9, 5), expected(0.5, -0.5));
+        assert_ray((4.0, 2.0), (9, 5), camera.forward);
+        assert_ray((2.0, 3.0), (9, 5), expected(-0.25, -0.25));
+
+        // A single pixel gives a finite ray, not a division by zero
+        assert_ray((0.0, 0.0), (1, 1), expected(-0.5, 0.5));
+    }
+
+    #[test]
+    fn span_buffers_are_kept_across_frames() {
+        // Linear colors of a span of an unusual size are easy to tell from
+        // other allocations. Each thread allocates them for the first frame,
+        // and later frames reuse them.
+        const SPAN_SIZE: usize = 777;
+        let renderer = Renderer::new();
+        renderer.set_num_threads(4);
+        renderer.set_span_size(SPAN_SIZE);
+        COUNTED_RENDERER.store(Arc::as_ptr(&renderer) as usize, Ordering::Relaxed);
+        COUNTED_SIZE.store(SPAN_SIZE * size_of::<(f64, f64, f64)>(), Ordering::Relaxed);
+        render_test_scene(&renderer, 64);
+        let first_frame = COUNTED_ALLOCATIONS.swap(0, Ordering::Relaxed);
+        assert_eq!(first_frame, 4);
+
+        for _ in 0..5 {
+            render_frame(&renderer, Duration::ZERO);
+        }
+        assert_eq!(COUNTED_ALLOCATIONS.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn panicking_render_thread_is_reported() {
+        // A thread panicking in the first pass of adaptive supersampling
+        // leaves no other thread waiting for it, and the caller gets the panic
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let renderer = Renderer::new();
+            renderer.set_scene(scene_test::populate_scene);
+            renderer.set_size((32, 32));
+            renderer.set_num_threads(4);
+            renderer.set_supersampling(2);
+            renderer.set_adaptive_supersampling(true);
+            let panicked = std::sync::atomic::AtomicBool::new(false);
+            renderer.set_background_callback(
+                move |_, _| {
+                    if !panicked.swap(true, Ordering::Relaxed) {
+                        panic!("background failed");
+                    }
+                    (0.0, 0.0, 0.0)
+                },
+                false,
+            );
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                render_frame(&renderer, Duration::ZERO);
+            }));
+            sender
+                .send(result.map_err(|payload| panic_message(&*payload)))
+                .unwrap();
+        });
+
+        let result = receiver.recv_timeout(Duration::from_secs(10));
+        let message = result.expect("render threads are stuck").unwrap_err();
+        assert!(message.contains("panicked: background failed"), "{message}");
+    }
+
+    fn populate_room(scene: &mut Scene, radius: f64) {
+        // Camera and a light inside a one-sided sphere, the environment green
+        scene.environment = Environment::Color(0.0, 1.0, 0.0);
+        scene.spheres.clear();
+        scene.spheres.push(Sphere {
+            pos: [0.0, 0.0, 0.0],
+            r: radius,
+            material: Material {
+                texture: Texture::Color(0.8, 0.8, 0.8),
+                specular: -1.0,
+                reflective: 0.0,
+                metallic: 0.0,
+                skip_lighting: false,
+                one_sided: true,
+                opacity: 1.0,
+                shadow_catcher: false,
+                emission: (0.0, 0.0, 0.0),
+                ripple: None,
+            },
+        });
+        scene.lights.clear();
+        scene.lights.extend([
+            Light::Ambient { intensity: 0.1 },
+            Light::Point {
+                intensity: 0.9,
+                pos: [0.0, 2.0, 0.0],
+            },
+        ]);
+        scene.camera = Camera {
+            pos: [0.0, 0.0, -1.0],
+            right: [1.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            forward: [0.0, 0.0, 1.0],
+            projection: Projection::Perspective,
+        };
+    }
+
+    #[test]
+    fn negative_radius_sphere_is_lit_inside() {
+        // Inside a sphere with a negative radius, the normal points inward,
+        // towards the light, so the walls are shaded. With a positive radius
+        // the one-sided walls face away, and only ambient light remains.
+        let render_room = |populate: PopulateScene| {
+            let renderer = Renderer::new();
+            render_test_scene(&renderer, 16);
+            renderer.set_scene(populate);
+            render_frame(&renderer, Duration::ZERO)
+        };
+        let green_of = |pixel: u32| pixel >> 8 & 0xff;
+        let red_of = |pixel: u32| pixel >> 16 & 0xff;
+
+        let room = render_room(|scene, _, _| populate_room(scene, -5.0));
+        let inside_out = render_room(|scene, _, _| populate_room(scene, 5.0));
+        for (&lit, &unlit) in room.iter().zip(&inside_out) {
+            // Walls everywhere, gray rather than the green environment
+            assert_eq!(red_of(lit), green_of(lit));
+            assert!(red_of(lit) > red_of(unlit) + 40, "{lit:08x} vs {unlit:08x}");
+        }
+    }
+
+    #[test]
+    fn reset_leaves_nothing_behind() {
+        // After accumulating frames of the juggler through another camera,
+        // reset() and the test scene render as by a new renderer
+        let renderer = Renderer::new();
+        renderer.set_accumulate(true);
+        renderer.set_scene(crate::scene_juggler::populate_scene);
+        renderer.set_size((32, 32));
+        let mut camera = renderer.get_active_camera();
+        camera.pan(1.0, 2.0);
+        renderer.set_camera(Some(camera));
+        for frame in 0..3 {
+            render_frame(&renderer, Duration::from_millis(300 * frame));
+        }
+
+        renderer.reset();
+        let after_reset = render_test_scene(&renderer, 32);
+        let fresh = Renderer::new();
+        fresh.set_accumulate(true);
+        assert_eq!(after_reset, render_test_scene(&fresh, 32));
+        assert_eq!(
+            *renderer.get_hdr_buffer().lock().unwrap(),
+            *fresh.get_hdr_buffer().lock().unwrap()
+        );
+        assert_eq!(renderer.data.lock().unwrap().accumulated_frames, 1);
+    }
+
+    #[test]
+    fn dark_reflection_cutoff_stays_close() {
+        // Skipping reflections below two steps of 8-bit color changes no
+        // channel by much more than that
+        let render = |cutoff| {
+            let renderer = Renderer::new();
+            renderer.set_dark_reflection_cutoff(cutoff);
+            let buffer = render_test_scene(&renderer, 64);
+            (buffer, renderer.get_stats().skipped_reflections)
+        };
+        let (traced, none_skipped) = render(0.0);
+        let (predicted, skipped) = render(2.0 / 255.0);
+        assert_eq!(none_skipped, 0);
+        assert!(skipped > 0);
+
+        let max_difference = traced
+            .iter()
+            .zip(&predicted)
+            .flat_map(|(&a, &b)| {
+                [0, 8, 16].map(|shift| ((a >> shift) & 0xff).abs_diff((b >> shift) & 0xff))
+            })
+            .max()
+            .unwrap();
+        assert!(max_difference <= 3, "max difference {max_difference}");
+    }
+
+    #[test]
+    fn frame_hash_is_stable() {
+        // Any change to shading or geometry of the test scene changes the
+        // hash, whatever the number of threads. After an intended change,
+        // update the hash here.
+        for num_threads in [1, 4] {
+            let renderer = Renderer::new();
+            renderer.set_num_threads(num_threads);
+            render_test_scene(&renderer, 64);
+            assert_eq!(
+                renderer.frame_hash(),
+                0xaad0_27ce_2b02_e12c,
+                "{num_threads} threads"
+            );
+        }
+    }
+
+    #[test]
+    fn interpolated_scenes_blend_and_fade() {
+        // Keyframe a is the test scene, b moves, grows and recolors its
+        // spheres, moves the camera, changes the lights and adds a sphere
+        let mut a = Scene {
+            camera: Renderer::new().get_active_camera(),
+            spheres: Vec::new(),
+            lights: Vec::new(),
+            environment: Environment::Color(0.0, 0.0, 0.0),
+            ambient_occlusion: Vec::new(),
+        };
+        scene_test::populate_scene(&mut a, Duration::ZERO, &SceneOptions::default());
+        let mut b = a.clone();
+        for sphere in b.spheres.iter_mut() {
+            sphere.pos[1] += 2.0;
+            sphere.r *= 2.0;
+            sphere.material.texture = Texture::Color(0.0, 1.0, 0.0);
+        }
+        b.spheres.push(b.spheres[0].clone());
+        b.camera.pos = [4.0, 3.0, -2.0];
+        b.camera.look_at([0.0, 0.0, 0.0]);
+        b.lights.truncate(1);
+
+        let close = |x: f64, y: f64| (x - y).abs() < 1e-9;
+        let same_camera = |x: &Camera, y: &Camera| {
+            [(x.pos, y.pos), (x.forward, y.forward), (x.up, y.up)]
+                .iter()
+                .all(|(p, q)| (0..3).all(|n| close(p[n], q[n])))
+        };
+        let interpolate = |a: &Scene, b: &Scene, t: f64| {
+            let mut scene = a.clone();
+            populate_interpolated(&mut scene, a, b, t);
+            scene
+        };
+
+        // The ends are the keyframes, without the sphere b adds at t = 0
+        let start = interpolate(&a, &b, 0.0);
+        assert!(start.spheres == a.spheres);
+        assert!(start.lights == a.lights);
+        assert!(same_camera(&start.camera, &a.camera));
+        let end = interpolate(&a, &b, 1.0);
+        assert_eq!(end.spheres.len(), b.spheres.len());
+        for (sphere, expected) in end.spheres.iter().zip(&b.spheres) {
+            assert!((0..3).all(|n| close(sphere.pos[n], expected.pos[n])));
+            assert!(close(sphere.r, expected.r));
+            assert!(sphere.material == expected.material);
+        }
+        assert!(end.lights == b.lights);
+        assert!(same_camera(&end.camera, &b.camera));
+
+        // Halfway, spheres are blended, the added one half faded in, and
+        // lights are those of b
+        let half = interpolate(&a, &b, 0.5);
+        assert_eq!(half.spheres.len(), b.spheres.len());
+        for ((sphere, from), to) in half.spheres.iter().zip(&a.spheres).zip(&b.spheres) {
+            assert!(close(sphere.pos[1], (from.pos[1] + to.pos[1]) / 2.0));
+            assert!(close(sphere.r, (from.r + to.r) / 2.0));
+        }
+        let Texture::Color(red, green, blue) = half.spheres[0].material.texture else {
+            panic!("plain colors blend to a plain color");
+        };
+        assert!(close(red, 0.5) && close(green, 0.6) && close(blue, 0.1));
+        assert!(close(half.spheres[4].material.opacity, 0.5));
+        assert!(half.lights == b.lights);
+
+        // A sphere only in the first keyframe fades out, and is gone at t = 1
+        let fading = interpolate(&b, &a, 0.25);
+        assert!(close(fading.spheres[4].material.opacity, 0.75));
+        assert_eq!(interpolate(&b, &a, 1.0).spheres.len(), a.spheres.len());
+    }
+}
@@ -3,10 +3,12 @@
 use std::time::Duration;
 
 use crate::renderer::{
-    Camera, Light, Scene, SceneOptions, Sphere, Texture::CheckerXZ, Texture::Color,
+    Camera, Environment, Light, Material, Projection, Ripple, Scene, SceneOptions, Sphere,
+    Texture::CheckerXZ, Texture::Color,
 };
 
-const SKY_COLOR: (f64, f64, f64) = (0.15, 0.25, 0.35); // Color when nothing hit
+const ENVIRONMENT: Environment = Environment::Color(0.15, 0.25, 0.35); // Color when nothing hit
+const RIPPLE_SPEED: f64 = 3.0; // Radians per second of the ground's longest wave
 
 pub fn populate_scene(
     scene: &mut Scene,
@@ -16,65 +18,115 @@ pub fn populate_scene(
     let secs = duration_since_start.as_secs_f64();
 
     // Color when nothing hit
-    scene.sky_color = SKY_COLOR;
+    scene.environment = ENVIRONMENT;
 
-    // Scene to render
-    scene.spheres = vec![
+    // Ground is either half reflective or a perfect mirror (option 1)
+    let (ground_specular, ground_reflective) = if scene_options.option_1 {
+        (10000.0, 1.0) // Extremely shiny, fully reflective
+    } else {
+        (1000.0, 0.5) // Very shiny, half reflective
+    };
+
+    // Ground reflections wobble like water (option 0)
+    let ground_ripple = scene_options.option_0.then_some(Ripple {
+        amplitude: 0.05,
+        wavelength: 2.0,
+        phase: (secs * scene_options.speed_0 + scene_options.offset_0) * RIPPLE_SPEED,
+    });
+
+    // Scene to render, reusing allocations of the previous frame
+    scene.spheres.clear();
+    scene.spheres.extend([
         Sphere {
             pos: [0.0, -1.0, -1.0],
             r: 1.0,
-            texture: Color(1.0, 0.0, 0.0), // Red
-            specular: 500.0,               // Shiny
-            reflective: 0.2,               // A bit reflective
-            skip_lighting: false,          // Regular lighting calculations
+            material: Material {
+                texture: Color(1.0, 0.0, 0.0), // Red
+                specular: 500.0,               // Shiny
+                reflective: 0.2,               // A bit reflective
+                metallic: 0.0,                 // Dielectric
+                skip_lighting: false,          // Regular lighting calculations
+                one_sided: false,              // Normal faces the incoming ray
+                opacity: 1.0,                  // Fully opaque
+                shadow_catcher: false,         // Visible surface
+                emission: (0.0, 0.0, 0.0),     // No glow
+                ripple: None,                  // Smooth
+            },
         },
         Sphere {
             pos: [2.0, 0.0, 0.0],
             r: 1.0,
-            texture: Color(0.0, 0.0, 1.0), // Blue
-            specular: 500.0,               // Shiny
-            reflective: 0.3,               // A bit more reflective
-            skip_lighting: false,          // Regular lighting calculations
+            material: Material {
+                texture: Color(0.0, 0.0, 1.0), // Blue
+                specular: 500.0,               // Shiny
+                reflective: 0.3,               // A bit more reflective
+                metallic: 0.0,                 // Dielectric
+                skip_lighting: false,          // Regular lighting calculations
+                one_sided: false,              // Normal faces the incoming ray
+                opacity: 1.0,                  // Fully opaque
+                shadow_catcher: false,         // Visible surface
+                emission: (0.0, 0.0, 0.0),     // No glow
+                ripple: None,                  // Smooth
+            },
         },
         Sphere {
             pos: [-2.0, 0.0, 0.0],
             r: 1.0,
-            texture: Color(0.0, 1.0, 0.0), // Green
-            specular: 10.0,                // Somewhat shiny
-            reflective: 0.4,               // Even more reflective
-            skip_lighting: false,          // Regular lighting calculations
+            material: Material {
+                texture: Color(0.0, 1.0, 0.0), // Green
+                specular: 10.0,                // Somewhat shiny
+                reflective: 0.4,               // Even more reflective
+                metallic: 0.0,                 // Dielectric
+                skip_lighting: false,          // Regular lighting calculations
+                one_sided: false,              // Normal faces the incoming ray
+                opacity: 1.0,                  // Fully opaque
+                shadow_catcher: false,         // Visible surface
+                emission: (0.0, 0.0, 0.0),     // No glow
+                ripple: None,                  // Smooth
+            },
         },
         Sphere {
             pos: [0.0, -5001.0, 0.0],
             r: 5000.0,
-            texture: CheckerXZ {
-                color1: (1.0, 1.0, 0.0),
-                color2: (1.0, 0.0, 1.0),
-                scale: 1.0,
-            }, // Yellow-magenta checkered texture, ground
-            specular: 1000.0,     // Very shiny
-            reflective: 0.5,      // Half reflective
-            skip_lighting: false, // Regular lighting calculations
+            material: Material {
+                texture: CheckerXZ {
+                    color1: (1.0, 1.0, 0.0),
+                    color2: (1.0, 0.0, 1.0),
+                    scale: 1.0,
+                    distance_scaling: false,
+                }, // Yellow-magenta checkered texture, ground
+                specular: ground_specular,
+                reflective: ground_reflective, // Recursion depth limits mirror bounces
+                metallic: 0.0,                 // Dielectric
+                skip_lighting: false,          // Regular lighting calculations
+                one_sided: false,              // Normal faces the incoming ray
+                opacity: 1.0,                  // Fully opaque
+                shadow_catcher: false,         // Visible surface
+                emission: (0.0, 0.0, 0.0),     // No glow
+                ripple: ground_ripple,         // Water-like waves (option 0)
+            },
         },
-    ];
+    ]);
 
     // Lights
-    scene.lights = vec![
+    scene.lights.clear();
+    scene.lights.extend([
         Light::Ambient { intensity: 0.2 },
         Light::Point {
-            intensity: 0.6,
+            intensity: 0.6 * scene_options.light_intensity,
             pos: [2.0, 1.0, -4.0],
         },
         Light::Directional {
             intensity: 0.2,
             dir: [1.0, 4.0, 0.0],
+            color: (1.0, 1.0, 1.0), // White
         },
-    ];
+    ]);
 
     // Camera
     const CAMERA_CYCLE_S: f64 = 15.0;
     let camera_distance = 5.0;
-    let camera_secs = secs * scene_options.speed_1;
+    let camera_secs = secs * scene_options.speed_1 + scene_options.offset_1;
     let camera_phase = (camera_secs % CAMERA_CYCLE_S) / CAMERA_CYCLE_S;
     let camera_angle = camera_phase * std::f64::consts::TAU;
     // DEBUG: let camera_angle = std::f64::consts::TAU / 8.0;
@@ -87,6 +139,7 @@ pub fn populate_scene(
         right: [1.0, 0.0, 0.0],
         up: [0.0, 1.0, 0.0],
         forward: [0.0, 0.0, 1.0],
+        projection: Projection::Perspective,
     };
     scene.camera.look_at([0.0, 0.0, 0.0]);
 }
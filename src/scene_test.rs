@@ -0,0 +1,155 @@
+// scene_test.rs - A fixed scene for comparing renders against reference
+// images. Nothing moves, and time and scene options are ignored, so that the
+// same settings always give the same image.
+
+use std::time::Duration;
+
+use crate::renderer::{
+    Camera, Environment, Light, Material, Projection, Renderer, Scene, SceneOptions, Sphere,
+    Texture::CheckerXZ, Texture::Color,
+};
+
+const ENVIRONMENT: Environment = Environment::Color(0.2, 0.3, 0.4); // Color when nothing hit
+
+pub fn populate_scene(
+    scene: &mut Scene,
+    _duration_since_start: Duration,
+    _scene_options: &SceneOptions,
+) {
+    // Color when nothing hit
+    scene.environment = ENVIRONMENT;
+
+    let material = |texture, specular, reflective| Material {
+        texture,
+        specular,
+        reflective,
+        metallic: 0.0,             // Dielectric
+        skip_lighting: false,      // Regular lighting calculations
+        one_sided: false,          // Normal faces the incoming ray
+        opacity: 1.0,              // Fully opaque
+        shadow_catcher: false,     // Visible surface
+        emission: (0.0, 0.0, 0.0), // No glow
+        ripple: None,              // Smooth
+    };
+
+    // Three spheres in a row on a checkered ground, the ground a sphere so
+    // large that it is flat in view
+    scene.spheres.clear();
+    scene.spheres.extend([
+        Sphere {
+            pos: [-2.2, 0.0, 0.0],
+            r: 1.0,
+            material: material(Color(1.0, 0.2, 0.2), 50.0, 0.0), // Red, dull
+        },
+        Sphere {
+            pos: [0.0, 0.0, 0.0],
+            r: 1.0,
+            material: material(Color(0.9, 0.9, 0.9), 500.0, 0.5), // White, mirror-like
+        },
+        Sphere {
+            pos: [2.2, 0.0, 0.0],
+            r: 1.0,
+            material: material(Color(0.2, 0.4, 1.0), -1.0, 0.0), // Blue, not shiny
+        },
+        Sphere {
+            pos: [0.0, -10001.0, 0.0],
+            r: 10000.0,
+            material: material(
+                CheckerXZ {
+                    color1: (0.9, 0.9, 0.9),
+                    color2: (0.1, 0.1, 0.1),
+                    scale: 1.0,
+                    distance_scaling: false,
+                },
+                -1.0,
+                0.2,
+            ), // Gray checkered ground
+        },
+    ]);
+
+    // One light of each kind
+    scene.lights.clear();
+    scene.lights.extend([
+        Light::Ambient { intensity: 0.2 },
+        Light::Point {
+            intensity: 0.6,
+            pos: [-3.0, 4.0, -3.0],
+        },
+        Light::Directional {
+            intensity: 0.2,
+            dir: [1.0, 2.0, -1.0],
+            color: (1.0, 0.9, 0.8), // Warm white
+        },
+    ]);
+
+    // Camera in front of the spheres, a bit above
+    scene.camera = Camera {
+        pos: [0.0, 1.5, -6.0],
+        right: [1.0, 0.0, 0.0],
+        up: [0.0, 1.0, 0.0],
+        forward: [0.0, 0.0, 1.0],
+        projection: Projection::Perspective,
+    };
+    scene.camera.look_at([0.0, 0.0, 0.0]);
+}
+
+#[allow(dead_code)]
+pub fn render(size: usize) -> Vec<u32> {
+    // Render the scene with fixed settings: one sample per pixel, no dithering
+    // and the default shading, which involves no random numbers
+    let renderer = Renderer::new();
+    renderer.set_scene(populate_scene);
+    renderer.set_size((size, size));
+    renderer.set_supersampling(1);
+    renderer.set_dithering(false);
+
+    renderer.start_render(Duration::ZERO, &SceneOptions::default());
+    renderer.wait_for_completion(false);
+    renderer.snapshot()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png;
+
+    // Reference render, written by write_reference() after an intended change
+    const REFERENCE: &[u8] = include_bytes!("../testdata/scene_test_32.png");
+    const REFERENCE_SIZE: usize = 32;
+    const TOLERANCE: u32 = 2; // Largest difference of a color channel, in 8-bit steps
+
+    #[test]
+    fn render_matches_reference() {
+        let (reference, width, height) = png::decode_rgba(REFERENCE).unwrap();
+        assert_eq!((width, height), (REFERENCE_SIZE, REFERENCE_SIZE));
+
+        let rendered = render(REFERENCE_SIZE);
+        let channel_difference = |a: u32, b: u32| {
+            [0, 8, 16, 24]
+                .iter()
+                .map(|shift| (a >> shift & 0xff).abs_diff(b >> shift & 0xff))
+                .max()
+                .unwrap()
+        };
+        let differing: Vec<_> = (0..rendered.len())
+            .filter(|&p| channel_difference(rendered[p], reference[p]) > TOLERANCE)
+            .map(|p| (p % REFERENCE_SIZE, p / REFERENCE_SIZE))
+            .collect();
+        assert!(
+            differing.is_empty(),
+            "pixels differ from the reference: {differing:?}"
+        );
+    }
+
+    #[test]
+    #[ignore = "writes the reference image, run after an intended change"]
+    fn write_reference() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/scene_test_32.png");
+        let rendered = render(REFERENCE_SIZE);
+        std::fs::write(
+            path,
+            png::encode_rgba(&rendered, REFERENCE_SIZE, REFERENCE_SIZE),
+        )
+        .unwrap();
+    }
+}
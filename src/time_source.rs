@@ -0,0 +1,99 @@
+// time_source.rs - Scene time of rendered frames, shared by window and file
+// rendering so that the same scene time renders the same image
+
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum TimeSource {
+    RealTime { start_time: Instant },     // Wall-clock time since start
+    FixedStep { fps: f64, frame: usize }, // Frame number divided by frame rate
+}
+
+impl TimeSource {
+    pub fn real_time() -> Self {
+        TimeSource::RealTime {
+            start_time: Instant::now(),
+        }
+    }
+
+    pub fn fixed_step(fps: f64) -> Self {
+        TimeSource::FixedStep { fps, frame: 0 }
+    }
+
+    pub fn restart(&mut self) {
+        match self {
+            TimeSource::RealTime { start_time } => *start_time = Instant::now(),
+            TimeSource::FixedStep { frame, .. } => *frame = 0,
+        }
+    }
+
+    pub fn skip(&mut self, duration: Duration) {
+        // Leave out a pause from wall-clock time, so that motion resumes
+        // where it stopped. Fixed steps only advance with rendered frames.
+        if let TimeSource::RealTime { start_time } = self {
+            *start_time += duration;
+        }
+    }
+
+    pub fn next_frame(&mut self) -> Duration {
+        // Scene time of the frame to render next
+        match self {
+            TimeSource::RealTime { start_time } => Instant::now().duration_since(*start_time),
+            TimeSource::FixedStep { fps, frame } => {
+                let duration = frame_time(*frame, *fps);
+                *frame += 1;
+                duration
+            }
+        }
+    }
+}
+
+pub fn frame_time(frame: usize, fps: f64) -> Duration {
+    // Scene time of a frame in fixed-step rendering
+    Duration::from_secs_f64(frame as f64 / fps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Renderer, SceneOptions};
+    use crate::scene_juggler;
+
+    #[test]
+    fn window_and_file_frames_match() {
+        // A window stepping frame by frame reaches one second at the same
+        // scene time and image as a file rendered at one second
+        let scene_options = SceneOptions::default();
+        let fps = 24.0;
+        let size = 48;
+        let new_renderer = || {
+            let renderer = Renderer::new();
+            renderer.set_scene(scene_juggler::populate_scene);
+            renderer.set_size((size, size));
+            renderer.set_supersampling(1);
+            renderer.set_dithering(false);
+            renderer
+        };
+
+        // Window: every frame rendered in turn, as if paused after one second
+        let window_renderer = new_renderer();
+        let mut time_source = TimeSource::fixed_step(fps);
+        let mut duration = Duration::ZERO;
+        for _ in 0..=24 {
+            duration = time_source.next_frame();
+            window_renderer.start_render(duration, &scene_options);
+            window_renderer.wait_for_completion(false);
+        }
+        let window_frame = window_renderer.snapshot();
+        assert_eq!(duration, Duration::from_secs(1));
+
+        // File: only the frame at one second
+        let file_renderer = new_renderer();
+        file_renderer.start_render(frame_time(24, fps), &scene_options);
+        file_renderer.wait_for_completion(false);
+        let file_frame = file_renderer.snapshot();
+
+        assert!(window_frame == file_frame);
+    }
+}
@@ -0,0 +1,94 @@
+// vec3.rs - A thin vector type with operators, for writing scenes
+
+use std::ops::{Add, Index, IndexMut, Mul, Neg, Sub};
+use vecmath::{
+    vec3_add, vec3_cross, vec3_dot, vec3_len, vec3_normalized, vec3_scale, vec3_sub, Vector3,
+};
+
+// Wraps a vecmath Vector3, convert with Vec3(v), v.into() or .0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec3(pub Vector3<f64>);
+
+impl Vec3 {
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Self([x, y, z])
+    }
+
+    #[allow(dead_code)]
+    pub fn dot(self, other: Vec3) -> f64 {
+        vec3_dot(self.0, other.0)
+    }
+
+    #[allow(dead_code)]
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3(vec3_cross(self.0, other.0))
+    }
+
+    #[allow(dead_code)]
+    pub fn len(self) -> f64 {
+        vec3_len(self.0)
+    }
+
+    #[allow(dead_code)]
+    pub fn normalized(self) -> Vec3 {
+        Vec3(vec3_normalized(self.0))
+    }
+}
+
+impl From<Vector3<f64>> for Vec3 {
+    fn from(v: Vector3<f64>) -> Self {
+        Vec3(v)
+    }
+}
+
+impl From<Vec3> for Vector3<f64> {
+    fn from(v: Vec3) -> Self {
+        v.0
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3(vec3_add(self.0, other.0))
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3(vec3_sub(self.0, other.0))
+    }
+}
+
+impl Mul<f64> for Vec3 {
+    type Output = Vec3;
+
+    fn mul(self, scale: f64) -> Vec3 {
+        Vec3(vec3_scale(self.0, scale))
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3 {
+        Vec3(vec3_scale(self.0, -1.0))
+    }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, index: usize) -> &f64 {
+        &self.0[index]
+    }
+}
+
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, index: usize) -> &mut f64 {
+        &mut self.0[index]
+    }
+}
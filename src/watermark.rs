@@ -0,0 +1,75 @@
+// watermark.rs - Logo composited over rendered files, e.g., for branded
+// exports, loaded from a PNG file with alpha
+
+use std::path::Path;
+
+use crate::png;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+pub struct Watermark {
+    pixels: Vec<u32>, // 0xAARRGGBB, colors premultiplied by alpha
+    width: usize,
+    height: usize,
+    corner: Corner,
+    opacity: f64,  // 0.0: invisible, 1.0: as in the file
+    margin: usize, // Pixels between the logo and the edges of the image
+}
+
+impl Watermark {
+    pub fn load(path: &Path, corner: Corner, opacity: f64, margin: usize) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|err| err.to_string())?;
+        let (pixels, width, height) = png::decode_rgba(&data)?;
+        Ok(Self {
+            pixels,
+            width,
+            height,
+            corner,
+            opacity: opacity.clamp(0.0, 1.0),
+            margin,
+        })
+    }
+
+    pub fn draw(&self, buffer: &mut [u32], width: usize, height: usize) {
+        // Logo in its corner of the image, cut off where it does not fit
+        let (width, height) = (width as isize, height as isize);
+        let (logo_width, logo_height) = (self.width as isize, self.height as isize);
+        let margin = self.margin as isize;
+        let left = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => margin,
+            Corner::TopRight | Corner::BottomRight => width - margin - logo_width,
+        };
+        let top = match self.corner {
+            Corner::TopLeft | Corner::TopRight => margin,
+            Corner::BottomLeft | Corner::BottomRight => height - margin - logo_height,
+        };
+
+        for y in top.max(0)..(top + logo_height).min(height) {
+            for x in left.max(0)..(left + logo_width).min(width) {
+                let logo = self.pixels[((y - top) * logo_width + x - left) as usize];
+                let target = &mut buffer[(y * width + x) as usize];
+                *target = over(logo, *target, self.opacity);
+            }
+        }
+    }
+}
+
+fn over(logo: u32, target: u32, opacity: f64) -> u32 {
+    // Logo over the target, both with colors premultiplied by alpha, which
+    // blends alpha the same way as colors
+    let alpha = (logo >> 24) as f64 / 255.0 * opacity;
+    [0, 8, 16, 24].into_iter().fold(0, |result, shift| {
+        let channel = |pixel: u32| (pixel >> shift & 0xff) as f64;
+        let blended = channel(logo) * opacity + channel(target) * (1.0 - alpha);
+        result | (blended.round().min(255.0) as u32) << shift
+    })
+}
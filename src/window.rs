@@ -5,47 +5,83 @@ use std::cmp::min;
 use std::num::NonZeroU32;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use winit::dpi::PhysicalSize;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 use winit::event::{ElementState, Event, KeyEvent, StartCause, WindowEvent};
-use winit::event_loop::{ControlFlow, EventLoopBuilder};
-use winit::keyboard::{Key, NamedKey};
-use winit::window::{Fullscreen, WindowBuilder};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopBuilder};
+use winit::window::{Fullscreen, WindowBuilder, WindowId};
 
-use crate::renderer::{SceneOptions, SharedRenderer};
+use crate::fps_counter::FPSCounter;
+use crate::key_bindings::{Action, KeyBindings};
+use crate::recorder::Recorder;
+use crate::renderer::{
+    color_to_u32, gamma_encode, Camera, Light, PopulateScene, QualityPreset, Scene, SceneOptions,
+    SharedRenderer, Sphere, FIELD_OF_VIEW,
+};
+use crate::text;
+use crate::time_source::TimeSource;
+use crate::{scene_juggler, scene_simple, scene_test};
 
 const WINDOW_REDRAW_PERIOD: f64 = 0.5; // Window redraw period in seconds
-const FPS_REFRESH_PERIOD: f64 = 0.25; // Update FPS counter this often
+const GIZMO_SIZE: isize = 6; // Half length of light marker lines in pixels
+const HELP_MARGIN: usize = 8; // Help panel distance from window edges in pixels
+const HELP_PADDING: usize = 6; // Space around help text inside the panel
+const HELP_TEXT_COLOR: u32 = 0x00ff_ffff; // White
+const GIZMO_COLOR: u32 = 0x00ff_ff00; // Yellow
+const CAMERA_TRANSITION_S: f64 = 0.5; // Camera blend time when switching scenes
+const OPTION_EASING_S: f64 = 0.3; // Speeds and light intensity reach new values in this time
+const MAX_LIGHT_INTENSITY: f64 = 3.0; // Upper limit of key light intensity scale
+const AUTO_FRAME_MAX_RADIUS: f64 = 100.0; // Larger spheres (e.g., ground) are not framed
+
+// Scenes to cycle through with the Tab key, by name
+pub const SCENES: [(&str, PopulateScene); 3] = [
+    ("juggler", scene_juggler::populate_scene),
+    ("simple", scene_simple::populate_scene),
+    ("test", scene_test::populate_scene),
+];
+
+// How the rendered image is scaled to the window size
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Scaling {
+    #[cfg_attr(feature = "serde", serde(rename = "nearest"))]
+    NearestNeighbor, // Blocky, copies displayed colors
+    Bilinear, // Smooth, interpolates linear colors before quantizing
+}
 
 #[derive(Debug, Clone, Copy)]
 enum UserEvent {
-    RequestRedraw,
+    RequestRedraw(WindowId), // Frame completed for the window
 }
 
 pub struct Window {
     renderer: SharedRenderer,
     size: Option<(usize, usize)>,
     title: String,
-    default_color: u32,
+    camera_transition: Duration, // Camera blend time when switching scenes
+    option_easing: Duration,     // Time for speeds and light intensity to change
+    power_saver: bool,           // Stop rendering while the window is unfocused
+    key_bindings: KeyBindings,
+    time_source: TimeSource, // Scene time of rendered frames
+    scene_index: usize,      // Index to SCENES of the first scene
+    scaling: Scaling,
 }
 
 impl Window {
     pub fn new(renderer: &SharedRenderer) -> Self {
         let renderer = renderer.clone();
 
-        // Store default color for window
-        let default_color;
-        {
-            // Read top-left pixel from render buffer
-            let buffer = renderer.get_buffer();
-            let buffer = buffer.lock().unwrap();
-            default_color = buffer[0];
-        }
-
         Self {
             renderer,
             size: None,
             title: "".to_string(),
-            default_color,
+            camera_transition: Duration::from_secs_f64(CAMERA_TRANSITION_S),
+            option_easing: Duration::from_secs_f64(OPTION_EASING_S),
+            power_saver: true,
+            key_bindings: KeyBindings::default(),
+            time_source: TimeSource::real_time(),
+            scene_index: 0,
+            scaling: Scaling::NearestNeighbor,
         }
     }
 
@@ -57,32 +93,79 @@ impl Window {
         self.title = title.to_string();
     }
 
+    pub fn set_time_source(&mut self, time_source: TimeSource) {
+        // TimeSource::fixed_step() renders the same frames as render_to_files()
+        self.time_source = time_source;
+    }
+
+    pub fn set_scene_index(&mut self, scene_index: usize) {
+        // Scene to show first, Tab continues from there
+        self.scene_index = scene_index % SCENES.len();
+        self.renderer.set_scene(SCENES[self.scene_index].1);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_option_easing(&mut self, easing: Duration) {
+        // Duration::ZERO: scene options change instantly when keys are pressed
+        self.option_easing = easing;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_power_saver(&mut self, power_saver: bool) {
+        // true: no new frames are rendered while the window is unfocused,
+        // toggled with a key as well
+        self.power_saver = power_saver;
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_camera_transition(&mut self, duration: Duration) {
+        // Duration::ZERO: switch cameras right away
+        self.camera_transition = duration;
+    }
+
+    #[allow(dead_code)]
     pub fn run(&self) {
+        run_windows(std::slice::from_ref(self));
+    }
+
+    fn create_winit_window(
+        &self,
+        event_loop: &EventLoop<UserEvent>,
+        index: usize,
+        num_windows: usize,
+    ) -> Arc<winit::window::Window> {
         let (mut width, mut height) = match self.size {
             Some(size) => size,
             None => self.renderer.get_size(),
         };
 
-        // Create a Winit Window and everything else needed to draw to it and handle events
-        let event_loop = EventLoopBuilder::<UserEvent>::with_user_event()
-            .build()
-            .unwrap();
         let winit_window = Arc::new(
             WindowBuilder::new()
                 .with_min_inner_size(PhysicalSize::new(width as u32, height as u32))
                 .with_inner_size(PhysicalSize::new(width as u32, height as u32))
                 .with_title(&self.title)
-                .build(&event_loop)
+                .build(event_loop)
                 .unwrap(),
         );
 
-        // Scale window 2x, 3x, ... depending on display resolution
+        // Scale window 2x, 3x, ... depending on display resolution, leaving
+        // room for all windows side by side
         // NOTE: Wayland returns None for current_monitor(), so use
         // the first entry in available_monitors() instead
         for monitor in winit_window.available_monitors() {
             let max_size = monitor.size();
+            let max_width = max_size.width as usize / num_windows;
 
-            let max_w_scale = max_size.width as usize / width;
+            let max_w_scale = max_width / width;
             let max_h_scale = max_size.height as usize / height;
             let max_scale = min(max_w_scale, max_h_scale);
 
@@ -92,232 +175,75 @@ impl Window {
                 height *= max_scale;
             } else {
                 // Render is too large for the display, use maximum window size
-                (width, height) = (max_size.width as usize, max_size.height as usize);
+                (width, height) = (max_width, max_size.height as usize);
             }
 
             // Even if None is returned, it is not an error
             let _ = winit_window.request_inner_size(PhysicalSize::new(width as u32, height as u32));
 
+            if num_windows > 1 {
+                // Place windows next to each other, not supported on Wayland
+                winit_window.set_outer_position(PhysicalPosition::new((index * width) as i32, 0));
+            }
+
             // Only consider the first monitor
             break;
         }
 
-        // Create a SoftBuffer Context and Surface for drawing pixels
-        let context = Context::new(winit_window.clone()).unwrap();
-        let mut surface = Surface::new(&context, winit_window.clone()).unwrap();
-
-        // Set completion callback to send a redraw request to the Winit window
-        {
-            let event_loop_proxy = event_loop.create_proxy();
-            self.renderer.set_completion_callback(move |frame_time| {
-                event_loop_proxy
-                    .send_event(UserEvent::RequestRedraw)
-                    .unwrap();
-
-                if false {
-                    // DEBUG: Print render duration
-                    println!("{:.03} s", frame_time.as_secs_f64())
-                }
-            });
-        }
-
-        let mut start_time = Instant::now(); // Set in StartCause::Init event handler
-        let mut fps_counter = FPSCounter::new();
-        let timer_duration = Duration::from_secs_f64(WINDOW_REDRAW_PERIOD);
+        winit_window
+    }
 
-        // Default scene options
-        let mut scene_options = SceneOptions {
-            speed_0: 1.0,
-            speed_1: 1.0,
-            option_0: false,
-            option_1: false,
+    fn update_title(
+        &self,
+        winit_window: &winit::window::Window,
+        fps: Option<f64>,
+        scene_options: &SceneOptions,
+        frozen: (bool, bool),
+    ) {
+        let (render_width, render_height) = self.renderer.get_size();
+        let fps = match fps {
+            Some(fps) => format!("{:.1}", fps),
+            None => "-".to_string(),
         };
+        let on_off = |option: bool| if option { "on" } else { "off" };
+        let speed = |speed: f64, frozen: bool| {
+            if frozen {
+                "frozen".to_string()
+            } else {
+                format!("{speed:.1}")
+            }
+        };
+        let samples_per_axis = self.renderer.get_supersampling();
 
-        let mut initialized = false;
-
-        // Run event loop
-        event_loop
-            .run(move |event, elwt| {
-                if false {
-                    // DEBUG: Print events
-                    println!("{:?}", event);
-                }
-
-                match event {
-                    // Handle start event
-                    Event::NewEvents(StartCause::Init) => {
-                        // Just started
-                        if false {
-                            elwt.set_control_flow(ControlFlow::WaitUntil(
-                                Instant::now() + timer_duration,
-                            ));
-                        } else {
-                            // DEBUG: No timer required, for now
-                            elwt.set_control_flow(ControlFlow::Wait);
-                        }
-
-                        // Start rendering the first frame
-                        start_time = Instant::now();
-                        fps_counter.reset();
-                        self.renderer.start_render(Duration::ZERO, &scene_options);
-                        initialized = true;
-                    }
-                    // Handle timer event
-                    Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
-                        // DEBUG: No timer required, for now
-                        if false {
-                            // Event timeout expired
-                            elwt.set_control_flow(ControlFlow::WaitUntil(
-                                Instant::now() + timer_duration,
-                            ));
-                            winit_window.request_redraw();
-                        }
-                    }
-                    // Handle requests from other threads
-                    Event::UserEvent(_) => {
-                        winit_window.request_redraw();
-                    }
-                    // Handle window redraw request event
-                    Event::WindowEvent {
-                        window_id,
-                        event: WindowEvent::RedrawRequested,
-                    } if window_id == winit_window.id() => {
-                        // Redraw requested
-                        if let (Some(width), Some(height)) = {
-                            let size = winit_window.inner_size();
-                            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
-                        } {
-                            // Resize surface if needed
-                            surface.resize(width, height).unwrap();
-
-                            if initialized {
-                                // Wait for all threads to complete
-                                self.renderer.wait_for_completion(false);
-                            }
-
-                            // Update title with new FPS every once in a while
-                            if let Some(fps) = fps_counter.new_frame(self.renderer.get_duration()) {
-                                let (render_width, render_height) = self.renderer.get_size();
-                                winit_window.set_title(
-                                    format!(
-                                        "{} - {}x{} - {:.1} fps",
-                                        self.title, render_width, render_height, fps
-                                    )
-                                    .as_str(),
-                                );
-                            }
-
-                            let fullscreen = winit_window.fullscreen().is_some();
-
-                            // Scale and copy shared render buffer contents to surface
-                            let mut buffer = surface.buffer_mut().unwrap();
-                            self.redraw(
-                                &mut buffer,
-                                (width.get() as usize, height.get() as usize),
-                                fullscreen,
-                            );
-
-                            // Update window contents with surface contents
-                            buffer.present().unwrap();
-
-                            if initialized {
-                                // Start rendering another frame
-                                let duration_since_start =
-                                    Instant::now().duration_since(start_time);
-                                self.renderer
-                                    .start_render(duration_since_start, &scene_options);
-                            }
-                        }
-                    }
-                    // Handle window close request event
-                    Event::WindowEvent {
-                        event: WindowEvent::CloseRequested,
-                        window_id,
-                    } if window_id == winit_window.id() => {
-                        // Window closed, exit event loop
-                        elwt.exit();
-                    }
-                    // Handle keyboard events
-                    Event::WindowEvent {
-                        event:
-                            WindowEvent::KeyboardInput {
-                                event:
-                                    KeyEvent {
-                                        logical_key,
-                                        state: ElementState::Pressed,
-                                        ..
-                                    },
-                                ..
-                            },
-                        window_id,
-                    } if window_id == winit_window.id() => {
-                        match logical_key.as_ref() {
-                            Key::Named(NamedKey::Escape) | Key::Character("q") => {
-                                // Exit event loop
-                                elwt.exit();
-                            }
-                            Key::Named(NamedKey::F11) | Key::Character("f") => {
-                                // Toggle fullscreen
-                                let fullscreen = if winit_window.fullscreen().is_some() {
-                                    None
-                                } else {
-                                    Some(Fullscreen::Borderless(None))
-                                };
-                                winit_window.set_fullscreen(fullscreen);
-                            }
-                            // Set (scene dependent) speed 0
-                            Key::Character("1") => {
-                                scene_options.speed_0 = 0.0;
-                            }
-                            Key::Character("2") => {
-                                scene_options.speed_0 = 0.5;
-                            }
-                            Key::Character("3") => {
-                                scene_options.speed_0 = 1.0;
-                            }
-                            Key::Character("4") => {
-                                scene_options.speed_0 = 1.5;
-                            }
-                            Key::Character("5") => {
-                                scene_options.speed_0 = 2.0;
-                            }
-                            // Set (scene dependent) speed 1
-                            Key::Character("6") => {
-                                scene_options.speed_1 = -2.0;
-                            }
-                            Key::Character("7") => {
-                                scene_options.speed_1 = -1.0;
-                            }
-                            Key::Character("8") => {
-                                scene_options.speed_1 = 0.0;
-                            }
-                            Key::Character("9") => {
-                                scene_options.speed_1 = 1.0;
-                            }
-                            Key::Character("0") => {
-                                scene_options.speed_1 = 2.0;
-                            }
-                            // Toggle (scene dependent) option 0
-                            Key::Character("a") => {
-                                scene_options.option_0 = !scene_options.option_0;
-                            }
-                            // Toggle (scene dependent) option 1
-                            Key::Character("b") => {
-                                scene_options.option_1 = !scene_options.option_1;
-                            }
-                            _ => {}
-                        }
-                    }
-                    _ => {}
-                }
-            })
-            .unwrap();
+        winit_window.set_title(
+            format!(
+                "{} - {}x{} - {}x{} samples - {} fps - speed {}/{} - light {:.1} - a: {} - b: {} - hidden: {:04b}",
+                self.title,
+                render_width,
+                render_height,
+                samples_per_axis,
+                samples_per_axis,
+                fps,
+                speed(scene_options.speed_0, frozen.0),
+                speed(scene_options.speed_1, frozen.1),
+                scene_options.light_intensity,
+                on_off(scene_options.option_0),
+                on_off(scene_options.option_1),
+                scene_options.hidden_groups,
+            )
+            .as_str(),
+        );
     }
 
-    fn redraw(&self, target_buffer: &mut [u32], target_size: (usize, usize), fullscreen: bool) {
+    fn redraw(
+        &self,
+        target_buffer: &mut [u32],
+        target_size: (usize, usize),
+        fullscreen: bool,
+    ) -> (usize, usize, usize, usize) {
         // Get default color for filling unused parts of the window
         let default_color = if !fullscreen {
-            self.default_color
+            color_to_u32(self.renderer.get_clear_color())
         } else {
             // Fullscreen, always black border
             0x0000_0000
@@ -368,74 +294,816 @@ impl Window {
                 }
             };
 
+        let bilinear =
+            |buffer: &mut [u32], left_x: usize, top_y: usize, width: usize, height: usize| {
+                // Scale linear colors of the rendered frame
+                let hdr_buffer = self.renderer.get_hdr_buffer();
+                let source_buffer = hdr_buffer.lock().unwrap();
+                scale_bilinear(
+                    &source_buffer,
+                    (render_width, render_height),
+                    (buffer, target_width),
+                    (left_x, top_y, width, height),
+                    self.renderer.get_gamma(),
+                );
+            };
+
+        let scale =
+            |buffer: &mut [u32], left_x: usize, top_y: usize, width: usize, height: usize| {
+                match self.scaling {
+                    Scaling::NearestNeighbor => {
+                        nearest_neighbor(buffer, left_x, top_y, width, height)
+                    }
+                    Scaling::Bilinear => bilinear(buffer, left_x, top_y, width, height),
+                }
+            };
+
         // Preserve aspect ratio, fill with default_color outside rendered image
         if render_width * target_height <= render_height * target_width {
             // Window is wider than rendered image
             let pad_x = (target_width - target_height) / 2;
-            nearest_neighbor(target_buffer, pad_x, 0, target_height, target_height);
+            scale(target_buffer, pad_x, 0, target_height, target_height);
             fill_x(target_buffer, pad_x, target_height);
+            (pad_x, 0, target_height, target_height)
         } else {
             // Window is taller than rendered image
             let pad_y = (target_height - target_width) / 2;
-            nearest_neighbor(target_buffer, 0, pad_y, target_width, target_width);
+            scale(target_buffer, 0, pad_y, target_width, target_width);
             fill_y(target_buffer, pad_y, target_width);
+            (0, pad_y, target_width, target_width)
+        }
+    }
+
+    fn draw_help(&self, target_buffer: &mut [u32], target_size: (usize, usize)) {
+        // One line per action, listing all keys bound to it
+        let mut lines: Vec<(String, String)> = Vec::new();
+        for (key, action) in self.key_bindings.bindings() {
+            let description = action.description();
+            match lines.iter_mut().find(|(_, line)| *line == description) {
+                Some((keys, _)) => *keys += &format!(", {}", key.name()),
+                None => lines.push((key.name(), description)),
+            }
+        }
+        let keys_width = lines.iter().map(|(keys, _)| keys.len()).max().unwrap_or(0);
+        let help_text = lines
+            .into_iter()
+            .map(|(keys, description)| format!("{keys:keys_width$}  {description}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Darken a panel behind the text, clipped to the window
+        let (target_width, target_height) = target_size;
+        let (text_width, text_height) = text::text_size(&help_text);
+        let panel_width = text_width + 2 * HELP_PADDING;
+        let panel_height = text_height + 2 * HELP_PADDING;
+        for y in HELP_MARGIN..min(HELP_MARGIN + panel_height, target_height) {
+            for x in HELP_MARGIN..min(HELP_MARGIN + panel_width, target_width) {
+                let pixel = &mut target_buffer[y * target_width + x];
+                *pixel = (*pixel >> 1) & 0x007f_7f7f; // Half brightness
+            }
+        }
+
+        text::draw_text(
+            target_buffer,
+            target_size,
+            (HELP_MARGIN + HELP_PADDING) as isize,
+            (HELP_MARGIN + HELP_PADDING) as isize,
+            &help_text,
+            HELP_TEXT_COLOR,
+        );
+    }
+
+    fn draw_light_gizmos(
+        &self,
+        target_buffer: &mut [u32],
+        target_size: (usize, usize),
+        image_rect: (usize, usize, usize, usize),
+    ) {
+        let (left_x, top_y, width, height) = image_rect;
+        let scene = self.renderer.get_scene();
+
+        for light in &scene.lights {
+            // Only lights with a position can be marked
+            let pos = match light {
+                Light::Point { pos, .. } => *pos,
+                _ => continue,
+            };
+
+            // Skip lights behind the camera
+            let Some((vx, vy)) = scene.camera.project(pos) else {
+                continue;
+            };
+
+            // Viewport coordinates to window coordinates
+            let x = left_x as f64 + (vx + 0.5) * (width - 1) as f64;
+            let y = top_y as f64 + (0.5 - vy) * (height - 1) as f64;
+            let (x, y) = (x.round() as isize, y.round() as isize);
+
+            // Draw a crosshair
+            draw_line(
+                target_buffer,
+                target_size,
+                (x - GIZMO_SIZE, y),
+                (x + GIZMO_SIZE, y),
+                GIZMO_COLOR,
+            );
+            draw_line(
+                target_buffer,
+                target_size,
+                (x, y - GIZMO_SIZE),
+                (x, y + GIZMO_SIZE),
+                GIZMO_COLOR,
+            );
         }
     }
 }
 
-struct FPSCounter {
-    last_update_time: Option<Instant>,
-    durations: Duration,
-    num_frames: usize,
+// State of one window while the event loop runs
+struct View<'a> {
+    window: &'a Window,
+    winit_window: Arc<winit::window::Window>,
+    _context: Context<Arc<winit::window::Window>>, // Kept alive with the surface
+    surface: Surface<Arc<winit::window::Window>, Arc<winit::window::Window>>,
+    time_source: TimeSource, // Restarted in StartCause::Init event handler
+    fps_counter: FPSCounter,
+    fps: Option<f64>,             // Latest FPS, shown in title
+    scene_options: SceneOptions,  // Rendered, eased toward target_options
+    target_options: SceneOptions, // Set by keys
+    frozen_speed_0: Option<f64>,  // Target speed to restore when unfrozen
+    frozen_speed_1: Option<f64>,
+    options_eased: Instant, // When scene_options were last eased
+    initialized: bool,
+    recorder: Option<Recorder>, // Active while recording
+    show_light_gizmos: bool,
+    show_help: bool,
+    power_saver: bool,
+    quality: QualityPreset, // Applied last, cycled with a key
+    time_jitter: bool,      // Supersamples spread over the frame interval
+    focused: bool,
+    paused_since: Option<Instant>, // Rendering paused by the power saver
+    scene_index: usize,            // Index to SCENES
+    camera_transition: Option<CameraTransition>,
 }
 
-impl FPSCounter {
-    fn new() -> Self {
-        Self {
-            last_update_time: None,
-            durations: Duration::ZERO,
-            num_frames: 0,
+// Camera blending from the previous scene to the current one
+struct CameraTransition {
+    from: Camera,
+    start_time: Instant,
+    camera_override: Option<Camera>, // Restored when done
+    scene: Scene,                    // For finding the camera of the current scene
+}
+
+// Run one event loop for all windows. Each window needs a renderer of its
+// own, as the renderer notifies a single window when a frame is completed.
+pub fn run_windows(windows: &[Window]) {
+    // Create Winit Windows and everything else needed to draw to them and handle events
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event()
+        .build()
+        .unwrap();
+
+    let mut views = Vec::with_capacity(windows.len());
+    for (index, window) in windows.iter().enumerate() {
+        let winit_window = window.create_winit_window(&event_loop, index, windows.len());
+
+        // Create a SoftBuffer Context and Surface for drawing pixels
+        let context = Context::new(winit_window.clone()).unwrap();
+        let surface = Surface::new(&context, winit_window.clone()).unwrap();
+
+        // Set completion callback to send a redraw request to the Winit window
+        {
+            let event_loop_proxy = event_loop.create_proxy();
+            let window_id = winit_window.id();
+            window.renderer.set_completion_callback(move |frame_time| {
+                event_loop_proxy
+                    .send_event(UserEvent::RequestRedraw(window_id))
+                    .unwrap();
+
+                log::debug!("Frame rendered in {:.03} s", frame_time.as_secs_f64());
+            });
         }
+
+        let scene_options = SceneOptions::default();
+
+        views.push(View {
+            window,
+            winit_window,
+            _context: context,
+            surface,
+            time_source: window.time_source,
+            fps_counter: FPSCounter::new(),
+            fps: None,
+            scene_options: scene_options.clone(),
+            target_options: scene_options,
+            frozen_speed_0: None,
+            frozen_speed_1: None,
+            options_eased: Instant::now(),
+            initialized: false,
+            recorder: None,
+            show_light_gizmos: false,
+            show_help: false,
+            power_saver: window.power_saver,
+            quality: QualityPreset::Balanced,
+            time_jitter: false,
+            focused: true,
+            paused_since: None,
+            scene_index: window.scene_index,
+            camera_transition: None,
+        });
     }
 
-    fn reset(&mut self) {
-        *self = Self {
-            last_update_time: None,
-            durations: Duration::ZERO,
-            num_frames: 0,
+    let timer_duration = Duration::from_secs_f64(WINDOW_REDRAW_PERIOD);
+
+    // Run event loop
+    event_loop
+        .run(move |event, elwt| {
+            log::trace!("{event:?}");
+
+            match event {
+                // Handle start event
+                Event::NewEvents(StartCause::Init) => {
+                    // Just started
+                    if false {
+                        elwt.set_control_flow(ControlFlow::WaitUntil(
+                            Instant::now() + timer_duration,
+                        ));
+                    } else {
+                        // DEBUG: No timer required, for now
+                        elwt.set_control_flow(ControlFlow::Wait);
+                    }
+
+                    // Start rendering the first frame in every window
+                    for view in views.iter_mut() {
+                        view.time_source.restart();
+                        view.fps_counter.reset();
+                        let duration_since_start = view.next_scene_time();
+                        view.window
+                            .renderer
+                            .start_render(duration_since_start, &view.scene_options);
+                        view.initialized = true;
+                    }
+                }
+                // Handle timer event
+                Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                    // DEBUG: No timer required, for now
+                    if false {
+                        // Event timeout expired
+                        elwt.set_control_flow(ControlFlow::WaitUntil(
+                            Instant::now() + timer_duration,
+                        ));
+                        for view in views.iter() {
+                            view.winit_window.request_redraw();
+                        }
+                    }
+                }
+                // Handle requests from other threads
+                Event::UserEvent(UserEvent::RequestRedraw(window_id)) => {
+                    // The window may have been closed already
+                    if let Some(view) = views
+                        .iter()
+                        .find(|view| view.winit_window.id() == window_id)
+                    {
+                        view.winit_window.request_redraw();
+                    }
+                }
+                // Handle window redraw request event
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::RedrawRequested,
+                } => {
+                    if let Some(view) = views
+                        .iter_mut()
+                        .find(|view| view.winit_window.id() == window_id)
+                    {
+                        view.redraw();
+                    }
+                }
+                // Handle focus changes, for the power saver
+                Event::WindowEvent {
+                    event: WindowEvent::Focused(focused),
+                    window_id,
+                } => {
+                    if let Some(view) = views
+                        .iter_mut()
+                        .find(|view| view.winit_window.id() == window_id)
+                    {
+                        view.focused = focused;
+                        view.update_pause();
+                    }
+                }
+                // Handle window close request event
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    window_id,
+                } => {
+                    // Window closed, exit event loop after the last one
+                    if let Some(index) = views
+                        .iter()
+                        .position(|view| view.winit_window.id() == window_id)
+                    {
+                        let view = views.remove(index);
+                        if let Some(active_recorder) = view.recorder {
+                            stop_recording(active_recorder);
+                        }
+                    }
+                    if views.is_empty() {
+                        elwt.exit();
+                    }
+                }
+                // Handle event loop exit
+                Event::LoopExiting => {
+                    for view in views.iter_mut() {
+                        if let Some(active_recorder) = view.recorder.take() {
+                            // Finalize recording in progress
+                            stop_recording(active_recorder);
+                        }
+                    }
+                }
+                // Handle keyboard events
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    logical_key,
+                                    state: ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        },
+                    window_id,
+                } => {
+                    let Some(view) = views
+                        .iter_mut()
+                        .find(|view| view.winit_window.id() == window_id)
+                    else {
+                        return;
+                    };
+
+                    match view.window.key_bindings.action(&logical_key.as_ref()) {
+                        Some(Action::Quit) => {
+                            // Exit event loop, closing all windows
+                            elwt.exit();
+                        }
+                        Some(action) => view.handle_action(action),
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+        })
+        .unwrap();
+}
+
+impl View<'_> {
+    fn update_pause(&mut self) {
+        // Pause while unfocused with the power saver on. The frame in
+        // progress still completes, render threads end after each frame.
+        let pause = self.power_saver && !self.focused;
+        match (pause, self.paused_since) {
+            (true, None) => self.paused_since = Some(Instant::now()),
+            (false, Some(paused_since)) => {
+                // Continue the animation from where it stopped
+                self.paused_since = None;
+                self.time_source.skip(paused_since.elapsed());
+                self.options_eased = Instant::now();
+                if self.initialized {
+                    let duration_since_start = self.next_scene_time();
+                    self.window
+                        .renderer
+                        .start_render(duration_since_start, &self.scene_options);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn next_scene_time(&mut self) -> Duration {
+        // Renderer's time provider, if any, overrides the time source
+        let frame_time = self.time_source.next_frame();
+        self.window.renderer.provided_time().unwrap_or(frame_time)
+    }
+
+    fn redraw(&mut self) {
+        let renderer = &self.window.renderer;
+
+        // Redraw requested
+        if let (Some(width), Some(height)) = {
+            let size = self.winit_window.inner_size();
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        } {
+            // Resize surface if needed
+            self.surface.resize(width, height).unwrap();
+
+            if self.initialized {
+                // Wait for all threads to complete
+                renderer.wait_for_completion(false);
+            }
+
+            if let Some(active_recorder) = self.recorder.as_mut() {
+                // Record the completed frame in render resolution. Stop
+                // recording if that fails, e.g., when the render size changed,
+                // keeping the frames recorded so far.
+                let buffer = renderer.snapshot();
+                if let Err(err) = active_recorder.add_frame(&buffer) {
+                    log::warn!("Recording stopped: {err}");
+                    if let Some(recorder) = self.recorder.take() {
+                        stop_recording(recorder);
+                    }
+                }
+            }
+
+            // Update title with new FPS every once in a while
+            if let Some(new_fps) = self.fps_counter.new_frame(renderer.get_duration()) {
+                self.fps = Some(new_fps);
+                self.window.update_title(
+                    &self.winit_window,
+                    self.fps,
+                    &self.scene_options,
+                    self.frozen(),
+                );
+            }
+
+            let fullscreen = self.winit_window.fullscreen().is_some();
+
+            // Scale and copy shared render buffer contents to surface
+            let mut buffer = self.surface.buffer_mut().unwrap();
+            let target_size = (width.get() as usize, height.get() as usize);
+            let image_rect = self.window.redraw(&mut buffer, target_size, fullscreen);
+
+            if self.show_light_gizmos {
+                // Debug overlay: mark light positions
+                self.window
+                    .draw_light_gizmos(&mut buffer, target_size, image_rect);
+            }
+
+            if self.show_help {
+                // Key bindings over the image
+                self.window.draw_help(&mut buffer, target_size);
+            }
+
+            // The surface expects the high byte to be zero, it holds alpha
+            for pixel in buffer.iter_mut() {
+                *pixel &= 0x00ff_ffff;
+            }
+
+            // Update window contents with surface contents
+            buffer.present().unwrap();
+
+            if self.initialized && self.paused_since.is_none() {
+                // Start rendering another frame
+                let duration_since_start = self.next_scene_time();
+                self.ease_scene_options(duration_since_start);
+                self.update_camera_transition(duration_since_start);
+                renderer.start_render(duration_since_start, &self.scene_options);
+            }
+        }
+    }
+
+    fn update_camera_transition(&mut self, duration_since_start: Duration) {
+        let renderer = &self.window.renderer;
+        let Some(transition) = self.camera_transition.as_mut() else {
+            return;
+        };
+
+        let transition_secs = self.window.camera_transition.as_secs_f64();
+        let t = if transition_secs > 0.0 {
+            transition.start_time.elapsed().as_secs_f64() / transition_secs
+        } else {
+            1.0
+        };
+
+        if t >= 1.0 {
+            // Done, back to the camera of the scene (or the override)
+            renderer.set_camera(transition.camera_override.clone());
+            self.camera_transition = None;
+            return;
+        }
+
+        // Camera the current scene would use at this time
+        let to = match &transition.camera_override {
+            Some(camera) => camera.clone(),
+            None => {
+                let (_, populate) = SCENES[self.scene_index];
+                populate(
+                    &mut transition.scene,
+                    duration_since_start,
+                    &self.scene_options,
+                );
+                transition.scene.camera.clone()
+            }
         };
+
+        // Ease in and out
+        let eased = t * t * (3.0 - 2.0 * t);
+        renderer.set_camera(Some(transition.from.lerp(&to, eased)));
     }
 
-    fn new_frame(&mut self, frame_duration: Duration) -> Option<f64> {
+    fn ease_scene_options(&mut self, duration_since_start: Duration) {
+        // Speeds and light intensity approach their targets smoothly, with
+        // 98% of a change done after option_easing. A frozen speed stops at
+        // once. Other options switch at once.
         let now = Instant::now();
+        let elapsed = now.duration_since(self.options_eased).as_secs_f64();
+        self.options_eased = now;
+        let easing = self.window.option_easing.as_secs_f64();
+        let t = if easing > 0.0 {
+            1.0 - (-4.0 * elapsed / easing).exp()
+        } else {
+            1.0
+        };
+
+        let (options, target) = (&mut self.scene_options, &self.target_options);
+        let ease = |value: f64, target: f64| value + (target - value) * t;
+        let speed_0 = match self.frozen_speed_0 {
+            Some(_) => 0.0,
+            None => ease(options.speed_0, target.speed_0),
+        };
+        let speed_1 = match self.frozen_speed_1 {
+            Some(_) => 0.0,
+            None => ease(options.speed_1, target.speed_1),
+        };
 
-        // Calculate FPS every FPS_REFRESH_PERIOD
-        self.num_frames += 1;
-        self.durations += frame_duration;
+        // Offsets keep the animations where they are at the new speeds, so
+        // that a frozen camera holds its angle and continues from it
+        let secs = duration_since_start.as_secs_f64();
+        options.offset_0 += secs * (options.speed_0 - speed_0);
+        options.offset_1 += secs * (options.speed_1 - speed_1);
+        options.speed_0 = speed_0;
+        options.speed_1 = speed_1;
+        options.light_intensity = ease(options.light_intensity, target.light_intensity);
+        options.option_0 = target.option_0;
+        options.option_1 = target.option_1;
+        options.hidden_groups = target.hidden_groups;
+    }
 
-        let fps = match self.last_update_time {
-            None => {
-                // First frame, skip updating FPS
-                self.last_update_time = Some(now);
-                None
+    fn frozen(&self) -> (bool, bool) {
+        // Whether speed 0 and speed 1 are frozen, for the title
+        (self.frozen_speed_0.is_some(), self.frozen_speed_1.is_some())
+    }
+
+    fn handle_action(&mut self, action: Action) {
+        let renderer = &self.window.renderer;
+        let scene_options = &mut self.target_options;
+
+        match action {
+            Action::Quit => {
+                // Handled by the event loop
             }
-            Some(last_time) => {
-                if now.duration_since(last_time).as_secs_f64() < FPS_REFRESH_PERIOD {
-                    // Not yet time to update FPS
+            Action::ToggleFullscreen => {
+                // Toggle fullscreen
+                let fullscreen = if self.winit_window.fullscreen().is_some() {
                     None
                 } else {
-                    // Time to update, calculate FPS
-                    let fps = self.num_frames as f64 / self.durations.as_secs_f64();
+                    Some(Fullscreen::Borderless(None))
+                };
+                self.winit_window.set_fullscreen(fullscreen);
+            }
+            // Set (scene dependent) speeds
+            Action::SetSpeed0(speed) => {
+                scene_options.speed_0 = speed;
+                self.frozen_speed_0 = None;
+            }
+            Action::SetSpeed1(speed) => {
+                scene_options.speed_1 = speed;
+                self.frozen_speed_1 = None;
+            }
+            // Stop a speed, and restore it when toggled again
+            Action::ToggleFreezeSpeed0 => match self.frozen_speed_0.take() {
+                Some(speed) => scene_options.speed_0 = speed,
+                None => {
+                    self.frozen_speed_0 = Some(scene_options.speed_0);
+                    scene_options.speed_0 = 0.0;
+                }
+            },
+            Action::ToggleFreezeSpeed1 => match self.frozen_speed_1.take() {
+                Some(speed) => scene_options.speed_1 = speed,
+                None => {
+                    self.frozen_speed_1 = Some(scene_options.speed_1);
+                    scene_options.speed_1 = 0.0;
+                }
+            },
+            // Toggle (scene dependent) options
+            Action::ToggleOption0 => {
+                scene_options.option_0 = !scene_options.option_0;
+            }
+            Action::ToggleOption1 => {
+                scene_options.option_1 = !scene_options.option_1;
+            }
+            // Hide or show a (scene dependent) group of objects
+            Action::ToggleHiddenGroup(group) => {
+                scene_options.hidden_groups ^= 1 << group;
+            }
+            // Dim or brighten the key light
+            Action::AdjustLightIntensity(step) => {
+                scene_options.light_intensity =
+                    (scene_options.light_intensity + step).clamp(0.0, MAX_LIGHT_INTENSITY);
+            }
+            // Decrease or increase supersampling
+            Action::DecreaseSupersampling => {
+                let samples_per_axis = renderer.get_supersampling();
+                renderer.set_supersampling(samples_per_axis.saturating_sub(1));
+            }
+            Action::IncreaseSupersampling => {
+                let samples_per_axis = renderer.get_supersampling();
+                renderer.set_supersampling(samples_per_axis + 1);
+            }
+            // Cycle through quality presets
+            Action::NextQualityPreset => {
+                self.quality = self.quality.next();
+                renderer.set_quality(self.quality);
+                log::info!("Quality: {:?}", self.quality);
+            }
+            // Spread supersamples over the frame interval, smearing motion
+            Action::ToggleTimeJitter => {
+                self.time_jitter = !self.time_jitter;
+                let interval = Duration::from_secs_f64(1.0 / crate::TARGET_FPS);
+                renderer.set_time_jitter(self.time_jitter.then_some(interval));
+                log::info!(
+                    "Time jitter: {}",
+                    if self.time_jitter { "on" } else { "off" }
+                );
+            }
+            // Switch to the next scene, blending cameras
+            Action::NextScene => {
+                // Start from the latest camera, even if a transition is in progress
+                let from = renderer.get_scene().camera;
+                let camera_override = match self.camera_transition.take() {
+                    Some(transition) => transition.camera_override,
+                    None => renderer.get_camera(),
+                };
 
-                    self.last_update_time = Some(now);
-                    self.durations = Duration::ZERO;
-                    self.num_frames = 0;
+                self.scene_index = (self.scene_index + 1) % SCENES.len();
+                renderer.set_scene(SCENES[self.scene_index].1);
 
-                    Some(fps)
+                self.camera_transition = Some(CameraTransition {
+                    from,
+                    start_time: Instant::now(),
+                    camera_override,
+                    scene: renderer.get_scene(),
+                });
+            }
+            // Fix the camera to fit the current scene, or release it
+            Action::ToggleAutoFrame => {
+                if renderer.get_camera().is_some() {
+                    renderer.set_camera(None);
+                } else {
+                    let scene = renderer.get_scene();
+                    let small_sphere = |sphere: &Sphere| sphere.r.abs() < AUTO_FRAME_MAX_RADIUS;
+                    if let Some(bounds) = scene.bounds_of(small_sphere) {
+                        let mut camera = scene.camera.clone();
+                        camera.frame(bounds, FIELD_OF_VIEW);
+                        renderer.set_camera(Some(camera));
+                    }
                 }
             }
-        };
+            // Toggle light position markers
+            Action::ToggleLightGizmos => {
+                self.show_light_gizmos = !self.show_light_gizmos;
+            }
+            // Toggle the key binding overlay
+            Action::ToggleHelp => {
+                self.show_help = !self.show_help;
+            }
+            // Toggle pausing while the window is unfocused
+            Action::TogglePowerSaver => {
+                self.power_saver = !self.power_saver;
+                self.update_pause();
+            }
+            // Toggle recording to a video file
+            Action::ToggleRecording => match self.recorder.take() {
+                Some(active_recorder) => stop_recording(active_recorder),
+                None => match Recorder::start(renderer.get_size()) {
+                    Ok(new_recorder) => {
+                        log::info!("Recording to {}", new_recorder.filename());
+                        self.recorder = Some(new_recorder);
+                    }
+                    Err(err) => log::warn!("Cannot start recording: {err}"),
+                },
+            },
+        }
+
+        // Show changed scene options right away
+        self.window.update_title(
+            &self.winit_window,
+            self.fps,
+            &self.target_options,
+            self.frozen(),
+        );
+    }
+}
+
+fn stop_recording(recorder: Recorder) {
+    match recorder.finish() {
+        Ok((filename, duration)) => {
+            log::info!("Recorded {:.1} s to {}", duration.as_secs_f64(), filename)
+        }
+        Err(err) => log::warn!("Recording failed: {err}"),
+    }
+}
+
+fn scale_bilinear(
+    source_buffer: &[(f64, f64, f64)],
+    source_size: (usize, usize),
+    target: (&mut [u32], usize),        // Buffer and its width
+    rect: (usize, usize, usize, usize), // Left, top, width and height in the target
+    gamma: f64,
+) {
+    // Interpolate linear colors, so that blending is not skewed by
+    // quantization, then gamma encode and convert each displayed pixel, as
+    // the renderer does
+    let (source_width, source_height) = source_size;
+    let (buffer, target_width) = target;
+    let (left_x, top_y, width, height) = rect;
+    let source_pos = |target: usize, target_len: usize, source_len: usize| {
+        // Pixel centers map to pixel centers
+        let pos = (target as f64 + 0.5) * source_len as f64 / target_len as f64 - 0.5;
+        let pos = pos.clamp(0.0, (source_len - 1) as f64);
+        let index = (pos as usize).min(source_len.saturating_sub(2));
+        (index, (index + 1).min(source_len - 1), pos - index as f64)
+    };
+    let sample = |x: usize, y: usize| {
+        // Clamp before blending, as displayed, so that a pixel brighter than
+        // white does not bleed into its neighbors
+        let (r, g, b) = source_buffer[y * source_width + x];
+        (r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0))
+    };
+    let lerp = |a: (f64, f64, f64), b: (f64, f64, f64), t: f64| {
+        (
+            a.0 + (b.0 - a.0) * t,
+            a.1 + (b.1 - a.1) * t,
+            a.2 + (b.2 - a.2) * t,
+        )
+    };
+    for target_y in top_y..(top_y + height) {
+        let (y0, y1, ty) = source_pos(target_y - top_y, height, source_height);
+        for target_x in left_x..(left_x + width) {
+            let (x0, x1, tx) = source_pos(target_x - left_x, width, source_width);
+            let top = lerp(sample(x0, y0), sample(x1, y0), tx);
+            let bottom = lerp(sample(x0, y1), sample(x1, y1), tx);
+            buffer[target_y * target_width + target_x] =
+                color_to_u32(gamma_encode(lerp(top, bottom, ty), gamma));
+        }
+    }
+}
+
+fn draw_line(
+    target_buffer: &mut [u32],
+    target_size: (usize, usize),
+    from: (isize, isize),
+    to: (isize, isize),
+    color: u32,
+) {
+    // Bresenham's line between two window positions, both ends included,
+    // clipped to the window
+    let (target_width, target_height) = target_size;
+    let (mut x, mut y) = from;
+    let (dx, dy) = ((to.0 - x).abs(), -(to.1 - y).abs());
+    let (step_x, step_y) = ((to.0 - x).signum(), (to.1 - y).signum());
+    let mut error = dx + dy;
+    loop {
+        if x >= 0 && y >= 0 && (x as usize) < target_width && (y as usize) < target_height {
+            target_buffer[y as usize * target_width + x as usize] = color;
+        }
+        if (x, y) == to {
+            break;
+        }
+        if 2 * error >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if 2 * error <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bilinear_upscale_blends_linear_colors() {
+        // A red pixel next to a black one, scaled 2x into the middle of a
+        // wider target. Between the two pixel centers, colors are linear
+        // blends, 0.75 and 0.25 red, whose average is the midpoint 0.5.
+        // Red brighter than white is clamped before blending.
+        for red in [1.0, 4.0] {
+            let source = [(red, 0.0, 0.0), (0.0, 0.0, 0.0)];
+            let mut target = vec![0; 6 * 2];
+            scale_bilinear(&source, (2, 1), (&mut target, 6), (1, 0, 4, 2), 1.0);
+            let row = [0, 0xffff_0000, 0xffbf_0000, 0xff3f_0000, 0xff00_0000, 0];
+            assert_eq!(target, [row, row].concat());
+
+            let red_of = |pixel: u32| (pixel >> 16 & 0xff) as f64 / 255.0;
+            let midpoint = (red_of(target[2]) + red_of(target[3])) / 2.0;
+            assert!((midpoint - 0.5).abs() < 1.0 / 255.0, "midpoint {midpoint}");
+        }
 
-        fps
+        // Gamma encoding follows blending, 0.25 red encodes to 0.5
+        let source = [(1.0, 0.0, 0.0), (0.0, 0.0, 0.0)];
+        let mut target = vec![0; 4];
+        scale_bilinear(&source, (2, 1), (&mut target, 4), (0, 0, 4, 1), 2.0);
+        assert_eq!(target, [0xffff_0000, 0xffdc_0000, 0xff7f_0000, 0xff00_0000]);
     }
 }